@@ -0,0 +1,144 @@
+use std::path::Path;
+
+use crate::{camera::Camera, config::parse_config};
+
+pub struct Keyframe {
+    pub time: f32,
+    pub camera: Camera,
+}
+
+/// A camera path through a list of keyframes, interpolated with a Catmull-Rom spline.
+///
+/// Keyframes hold every field of a bookmark, with `yaw` and `pitch` in degrees. The yaw is
+/// interpolated as written, so going from 0 to 360 turns the camera a full circle. There is no
+/// focus to animate, as the renderer models a pinhole camera without depth of field.
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    /// Loads `[[keyframe]]` tables holding a `time` in seconds next to the camera fields of a
+    /// bookmark.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        let sections =
+            parse_config(&content).map_err(|err| format!("{}: {err}", path.display()))?;
+
+        let keyframes = sections
+            .iter()
+            .filter(|section| section.name == "keyframe")
+            .map(|section| {
+                Ok(Keyframe {
+                    time: section.number("time")?,
+                    camera: Camera::from_section(section)?,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        if keyframes.is_empty() {
+            return Err(format!("{}: no keyframes", path.display()));
+        }
+        Ok(Self::new(keyframes))
+    }
+
+    fn new(mut keyframes: Vec<Keyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { keyframes }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().unwrap().time - self.keyframes[0].time
+    }
+
+    /// Returns the camera at `time` seconds after the first keyframe.
+    pub fn sample(&self, time: f32) -> Camera {
+        let time = self.keyframes[0].time + time;
+        let last = self.keyframes.len() - 1;
+        if time >= self.keyframes[last].time {
+            return self.keyframes[last].camera;
+        }
+
+        let segment = self
+            .keyframes
+            .windows(2)
+            .position(|pair| time < pair[1].time)
+            .unwrap();
+
+        let k0 = &self.keyframes[segment.saturating_sub(1)];
+        let k1 = &self.keyframes[segment];
+        let k2 = &self.keyframes[segment + 1];
+        let k3 = &self.keyframes[(segment + 2).min(last)];
+
+        let duration = k2.time - k1.time;
+        let s = ((time - k1.time) / duration).clamp(0.0, 1.0);
+
+        let spline = |value: fn(&Camera) -> f32| {
+            let p1 = value(&k1.camera);
+            let p2 = value(&k2.camera);
+            let m1 = tangent(k0, k2, value) * duration;
+            let m2 = tangent(k1, k3, value) * duration;
+            hermite(p1, m1, p2, m2, s)
+        };
+        let lerp = |value: fn(&Camera) -> f32| {
+            value(&k1.camera) + (value(&k2.camera) - value(&k1.camera)) * s
+        };
+
+        let mut camera = k1.camera;
+        camera.target.x = spline(|camera| camera.target.x);
+        camera.target.y = spline(|camera| camera.target.y);
+        camera.target.z = spline(|camera| camera.target.z);
+        camera.radius = spline(|camera| camera.radius).max(0.0);
+        camera.yaw = spline(|camera| camera.yaw);
+        camera.pitch = spline(|camera| camera.pitch);
+        camera.fov_y = spline(|camera| camera.fov_y);
+        camera.near = lerp(|camera| camera.near);
+        camera.far = lerp(|camera| camera.far);
+        camera.eye_separation = lerp(|camera| camera.eye_separation);
+        camera
+    }
+}
+
+/// Finite difference tangent of a non-uniform Catmull-Rom spline.
+fn tangent(previous: &Keyframe, next: &Keyframe, value: fn(&Camera) -> f32) -> f32 {
+    let duration = next.time - previous.time;
+    if duration > 0.0 {
+        (value(&next.camera) - value(&previous.camera)) / duration
+    } else {
+        0.0
+    }
+}
+
+fn hermite(p1: f32, m1: f32, p2: f32, m2: f32, s: f32) -> f32 {
+    let s2 = s * s;
+    let s3 = s2 * s;
+    (2.0 * s3 - 3.0 * s2 + 1.0) * p1
+        + (s3 - 2.0 * s2 + s) * m1
+        + (-2.0 * s3 + 3.0 * s2) * p2
+        + (s3 - s2) * m2
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{PI, TAU};
+
+    use glam::Vec3;
+
+    use super::*;
+
+    /// Yaw is interpolated as written, so keyframes at 0° and 360° turn the camera once around.
+    #[test]
+    fn full_turns_keep_their_yaw() {
+        let keyframe = |time, yaw| {
+            let mut camera = Camera::new(Vec3::ZERO, 3.0);
+            camera.yaw = yaw;
+            Keyframe { time, camera }
+        };
+        let path = CameraPath::new(vec![keyframe(0.0, 0.0), keyframe(4.0, TAU)]);
+
+        for (time, yaw) in [(1.0, PI / 2.0), (2.0, PI), (3.0, 1.5 * PI), (4.0, TAU)] {
+            let sampled = path.sample(time).yaw;
+            assert!((sampled - yaw).abs() < 1e-4, "{time}: {sampled} != {yaw}");
+        }
+    }
+}
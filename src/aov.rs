@@ -0,0 +1,72 @@
+/// Arbitrary output variables, auxiliary images of the first hit of every pixel that the path
+/// tracer writes next to the beauty pass.
+///
+/// Albedo, normal and depth are averaged over the samples of the pixel like the beauty pass.
+/// Position and the IDs are sampled at the pixel center, as averages across edges would belong
+/// to no surface. The sky has zero normal, depth and position, and IDs of -1, the ground plane
+/// IDs of -2.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aov {
+    Albedo,
+    /// World space shading normal, facing the camera.
+    Normal,
+    /// Distance from the camera to the first hit along the camera ray.
+    Depth,
+    /// World space position, with zero alpha for the sky.
+    Position,
+    /// Index of the material in the scene's material library.
+    MaterialId,
+    /// Index of the instance in the acceleration structure.
+    InstanceId,
+}
+
+impl Aov {
+    pub const ALL: [Self; 6] = [
+        Self::Albedo,
+        Self::Normal,
+        Self::Depth,
+        Self::Position,
+        Self::MaterialId,
+        Self::InstanceId,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Albedo => "albedo",
+            Self::Normal => "normal",
+            Self::Depth => "depth",
+            Self::Position => "position",
+            Self::MaterialId => "material-id",
+            Self::InstanceId => "instance-id",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+
+    /// Channel names in EXR files, taken from the leading channels of the AOV image.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Self::Albedo => &["R", "G", "B"],
+            Self::Normal | Self::Position => &["X", "Y", "Z"],
+            Self::Depth => &["Z"],
+            Self::MaterialId | Self::InstanceId => &["id"],
+        }
+    }
+
+    /// Whether half floats would lose relevant precision, like positions far from the origin or
+    /// IDs above 2048.
+    pub fn needs_full_precision(self) -> bool {
+        matches!(
+            self,
+            Self::Depth | Self::Position | Self::MaterialId | Self::InstanceId
+        )
+    }
+
+    /// The following AOV, none after the last one, which returns to the beauty pass.
+    pub fn next(self) -> Option<Self> {
+        let index = Self::ALL.iter().position(|a| *a == self).unwrap();
+        Self::ALL.get(index + 1).copied()
+    }
+}
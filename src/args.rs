@@ -0,0 +1,583 @@
+use std::{
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use glam::Vec3;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    aov::Aov,
+    bookmark::{Bookmarks, NUM_SLOTS},
+    camera::{Camera, Projection, SensorSize, DEFAULT_FAR, DEFAULT_NEAR},
+    gpu::{parse_backends, AdapterChoice, GpuOptions},
+    model::BoundingSphere,
+    output::OutputOptions,
+    renderer::RenderSettings,
+    sampler::Sampler,
+};
+
+const USAGE: &str = "\
+Usage: raytracer [OPTIONS]
+
+Camera (drag to orbit, scroll to zoom, F to frame the model):
+  --fov <DEGREES>           Vertical field of view (default: 90)
+  --focal-length <MM>       Lens focal length, overrides --fov
+  --sensor <NAME|WxH>       Sensor used with --focal-length: full-frame, aps-c,
+                            super35, micro-four-thirds or e.g. 36x24 (default: full-frame)
+  --near <DISTANCE>         Near clip distance (default: 0.1)
+  --far <DISTANCE>          Far clip distance (default: 100, or farther to fit the scene
+                            or --near)
+  --projection <NAME>       perspective, orthographic, equirectangular or
+                            stereo-equirectangular, cycle with P (default: perspective)
+  --eye-separation <DISTANCE>
+                            Eye distance of stereo projections (default: 0.065)
+
+Bookmarks (press 0-9 to restore, Ctrl+0-9 to save):
+  --bookmarks <PATH>        Bookmark file (default: bookmarks.toml)
+  --bookmark <SLOT>         Start from a saved bookmark
+
+Output (F12 saves the window as screenshot-<TIME>.exr, Shift+F12 as shown to a .png):
+  --size <WxH>              Window and image size (default: 1920x1080)
+  --samples <N>             Samples per pixel of rendered images (default: 256)
+  --seed <N>                Seed of the random sequences, the same seed, scene, camera and
+                            sample count reproduce the same image (default: random, logged)
+  --sampler <NAME>          sobol, halton or blue-noise, cycle with S (default: sobol)
+
+Accumulation in the window:
+  --target-samples <N>      Stop accumulating after N samples per pixel
+  --time-budget <SECONDS>   Stop accumulating after SECONDS of rendering
+  --samples-per-dispatch <N>
+                            Samples per pixel added by every frame (default: 1)
+  --no-reprojection         Restart the accumulation on camera changes instead of
+                            reprojecting the samples of surfaces that stay visible
+
+Path tracing:
+  --max-depth <N>           Maximum number of bounces per path (default: 10)
+  --rr-min-depth <N>        Bounces before Russian roulette may end a path (default: 3)
+  --rr-min-probability <P>  Lowest survival probability of Russian roulette (default: 0.05)
+  --adaptive-threshold <E>  Stop sampling pixels whose relative standard error is below E,
+                            which saves time but does not give their samples to noisier
+                            pixels, 0 samples all pixels equally, H shows the samples per
+                            pixel (default: 0.01)
+  --adaptive-min-samples <N>
+                            Samples per pixel before adaptive sampling starts (default: 32)
+  --denoise                 Filter the noise out of the window and rendered images,
+                            toggle with D
+  --output <PATH>           Output image, '#' is replaced by the frame number of animations,
+                            .exr and .pfm keep linear radiance, .png and .jpg are clamped
+                            (default: render.png, frame_####.png for animations)
+  --aovs <LIST>             Also write these comma separated AOVs next to --output, e.g.
+                            render.albedo.png: albedo, normal, depth, position, material-id,
+                            instance-id or all. .exr files store them as layers of the
+                            same file, cycle through them in the window with V
+  --exr-half                Store the colors of .exr files as half floats
+  --png-16bit               Write 16 instead of 8 bits per channel to .png files
+  --headless                Render a single image without opening a window
+  --tile-size <N>           Render images in tiles of NxN pixels, for sizes beyond the
+                            texture limits of the gpu
+  --checkpoint <PATH>       Save the accumulation to PATH periodically and when the window
+                            closes, finished images remove it
+  --checkpoint-interval <SECONDS>
+                            Time between checkpoints (default: 300)
+  --resume                  Continue the accumulation saved in --checkpoint
+  --cpu                     Render with the cpu reference path tracer, implies --headless
+
+Animation:
+  --animation <PATH>        Render the keyframes in PATH to an image sequence
+  --fps <N>                 Frames per second of the sequence (default: 30)
+
+Gpu:
+  --backend <LIST>          Comma separated graphics APIs to search for adapters: vulkan,
+                            metal, dx12, gl or all (default: vulkan)
+  --adapter <INDEX|NAME>    Render on the adapter with this index or part of its name,
+                            instead of the most capable one with every required feature
+  --list-adapters           Print the adapters of --backend and their missing features
+
+Distributed rendering:
+  --workers <LIST>          Render images on the comma separated worker addresses, e.g.
+                            box1:7878,box2:7878, merging tiles and sample ranges
+  --worker <ADDRESS>        Run as a worker for --workers, listening on e.g. 0.0.0.0:7878
+
+Development:
+  --watch-shaders           Recompile the shaders when their files in src/shaders
+                            change, keeping the previous ones if they fail to compile
+
+Comparison:
+  --compare <REFERENCE> <IMAGE>
+                            Print RMSE, PSNR, SSIM and FLIP of IMAGE against REFERENCE,
+                            and write an error heatmap to --output if given
+
+  -h, --help                Print this help";
+
+pub struct Args {
+    pub fov: Option<f32>,
+    pub focal_length: Option<f32>,
+    pub sensor: SensorSize,
+    pub near: f32,
+    /// Framing the scene pushes the far distance back unless it is given.
+    pub far: Option<f32>,
+    pub projection: Option<Projection>,
+    pub eye_separation: Option<f32>,
+    pub bookmarks: PathBuf,
+    pub bookmark: Option<usize>,
+    pub size: PhysicalSize<u32>,
+    pub samples: u32,
+    pub seed: u32,
+    pub sampler: Sampler,
+    pub settings: RenderSettings,
+    pub denoise: bool,
+    pub target_samples: Option<u32>,
+    pub time_budget: Option<Duration>,
+    pub samples_per_dispatch: u32,
+    pub reprojection: bool,
+    pub output: Option<String>,
+    pub aovs: Vec<Aov>,
+    pub output_options: OutputOptions,
+    pub tile_size: Option<u32>,
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    pub resume: bool,
+    pub headless: bool,
+    pub cpu: bool,
+    pub animation: Option<PathBuf>,
+    pub fps: f32,
+    pub workers: Vec<String>,
+    pub worker: Option<String>,
+    pub gpu: GpuOptions,
+    pub list_adapters: bool,
+    pub watch_shaders: bool,
+    pub compare: Option<(PathBuf, PathBuf)>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            fov: None,
+            focal_length: None,
+            sensor: SensorSize::FULL_FRAME,
+            near: 0.1,
+            far: None,
+            projection: None,
+            eye_separation: None,
+            bookmarks: PathBuf::from("bookmarks.toml"),
+            bookmark: None,
+            size: PhysicalSize::new(1920, 1080),
+            samples: 256,
+            seed: random_seed(),
+            sampler: Sampler::Sobol,
+            settings: RenderSettings::default(),
+            denoise: false,
+            target_samples: None,
+            time_budget: None,
+            samples_per_dispatch: 1,
+            reprojection: true,
+            output: None,
+            aovs: Vec::new(),
+            output_options: OutputOptions::default(),
+            tile_size: None,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(300),
+            resume: false,
+            headless: false,
+            cpu: false,
+            animation: None,
+            fps: 30.0,
+            workers: Vec::new(),
+            worker: None,
+            gpu: GpuOptions::default(),
+            list_adapters: false,
+            watch_shaders: false,
+            compare: None,
+        }
+    }
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, String> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    fn parse_from(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--fov" => parsed.fov = Some(parse_float(&arg, args.next())?),
+                "--focal-length" => parsed.focal_length = Some(parse_float(&arg, args.next())?),
+                "--sensor" => parsed.sensor = parse_sensor(&arg, args.next())?,
+                "--near" => parsed.near = parse_float(&arg, args.next())?,
+                "--far" => parsed.far = Some(parse_float(&arg, args.next())?),
+                "--projection" => parsed.projection = Some(parse_projection(&arg, args.next())?),
+                "--eye-separation" => parsed.eye_separation = Some(parse_float(&arg, args.next())?),
+                "--bookmarks" => parsed.bookmarks = parse_value(&arg, args.next())?,
+                "--bookmark" => parsed.bookmark = Some(parse_value(&arg, args.next())?),
+                "--size" => parsed.size = parse_size(&arg, args.next())?,
+                "--samples" => parsed.samples = parse_value(&arg, args.next())?,
+                "--seed" => parsed.seed = parse_value(&arg, args.next())?,
+                "--sampler" => parsed.sampler = parse_sampler(&arg, args.next())?,
+                "--max-depth" => parsed.settings.max_depth = parse_value(&arg, args.next())?,
+                "--rr-min-depth" => parsed.settings.rr_min_depth = parse_value(&arg, args.next())?,
+                "--rr-min-probability" => {
+                    parsed.settings.rr_min_probability = parse_float(&arg, args.next())?
+                }
+                "--adaptive-threshold" => {
+                    parsed.settings.adaptive_threshold = parse_float(&arg, args.next())?
+                }
+                "--adaptive-min-samples" => {
+                    parsed.settings.adaptive_min_samples = parse_value(&arg, args.next())?
+                }
+                "--denoise" => parsed.denoise = true,
+                "--target-samples" => parsed.target_samples = Some(parse_value(&arg, args.next())?),
+                "--time-budget" => parsed.time_budget = Some(parse_duration(&arg, args.next())?),
+                "--samples-per-dispatch" => {
+                    parsed.samples_per_dispatch = parse_value(&arg, args.next())?
+                }
+                "--no-reprojection" => parsed.reprojection = false,
+                "--output" => parsed.output = Some(parse_value(&arg, args.next())?),
+                "--aovs" => parsed.aovs = parse_aovs(&arg, args.next())?,
+                "--exr-half" => parsed.output_options.half = true,
+                "--png-16bit" => parsed.output_options.png_16_bit = true,
+                "--tile-size" => parsed.tile_size = Some(parse_value(&arg, args.next())?),
+                "--checkpoint" => parsed.checkpoint = Some(parse_value(&arg, args.next())?),
+                "--checkpoint-interval" => {
+                    parsed.checkpoint_interval = parse_duration(&arg, args.next())?
+                }
+                "--resume" => parsed.resume = true,
+                "--headless" => parsed.headless = true,
+                "--cpu" => parsed.cpu = true,
+                "--animation" => parsed.animation = Some(parse_value(&arg, args.next())?),
+                "--fps" => parsed.fps = parse_float(&arg, args.next())?,
+                "--workers" => {
+                    let workers: String = parse_value(&arg, args.next())?;
+                    parsed.workers = workers.split(',').map(str::to_string).collect();
+                }
+                "--worker" => parsed.worker = Some(parse_value(&arg, args.next())?),
+                "--backend" => parsed.gpu.backends = parse_backend(&arg, args.next())?,
+                "--adapter" => {
+                    let adapter: String = parse_value(&arg, args.next())?;
+                    parsed.gpu.adapter = Some(AdapterChoice::parse(&adapter));
+                }
+                "--list-adapters" => parsed.list_adapters = true,
+                "--watch-shaders" => parsed.watch_shaders = true,
+                "--compare" => {
+                    parsed.compare = Some((
+                        parse_value(&arg, args.next())?,
+                        parse_value(&arg, args.next())?,
+                    ))
+                }
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown argument '{arg}'\n\n{USAGE}")),
+            }
+        }
+
+        if parsed.fov.is_some() && parsed.focal_length.is_some() {
+            return Err("--fov and --focal-length are mutually exclusive".into());
+        }
+        if parsed.fov.is_some_and(|fov| fov <= 0.0 || fov >= 180.0) {
+            return Err("--fov must be in (0, 180)".into());
+        }
+        if parsed
+            .focal_length
+            .is_some_and(|focal_length| focal_length <= 0.0)
+        {
+            return Err("--focal-length must be positive".into());
+        }
+        if !(parsed.sensor.width > 0.0 && parsed.sensor.height > 0.0) {
+            return Err("--sensor must have a positive width and height".into());
+        }
+        if parsed
+            .eye_separation
+            .is_some_and(|eye_separation| eye_separation < 0.0)
+        {
+            return Err("--eye-separation must not be negative".into());
+        }
+        if parsed.near <= 0.0 {
+            return Err(format!("--near {} must be positive", parsed.near));
+        }
+        if let Some(far) = parsed.far.filter(|&far| far <= parsed.near) {
+            return Err(format!(
+                "invalid clip range: near {} must be less than far {far}",
+                parsed.near
+            ));
+        }
+        if parsed.bookmark.is_some_and(|slot| slot >= NUM_SLOTS) {
+            return Err(format!("bookmark slot must be less than {NUM_SLOTS}"));
+        }
+        if parsed.size.width == 0 || parsed.size.height == 0 {
+            return Err("--size must not be empty".into());
+        }
+        if parsed.animation.is_some() && !parsed.output().contains('#') {
+            return Err("--output needs a '#' placeholder for the frame number".into());
+        }
+        if parsed.fps <= 0.0 {
+            return Err("--fps must be positive".into());
+        }
+        if parsed.samples == 0 || parsed.target_samples == Some(0) {
+            return Err("--samples and --target-samples must be at least 1".into());
+        }
+        if parsed.time_budget == Some(Duration::ZERO) || parsed.checkpoint_interval.is_zero() {
+            return Err("--time-budget and --checkpoint-interval must be positive".into());
+        }
+        if parsed.samples_per_dispatch == 0 {
+            return Err("--samples-per-dispatch must be at least 1".into());
+        }
+        if !parsed.aovs.is_empty() && parsed.cpu {
+            return Err("--aovs requires the gpu renderer".into());
+        }
+        if let Some(tile_size) = parsed.tile_size {
+            if tile_size == 0 {
+                return Err("--tile-size must be at least 1".into());
+            }
+            if !parsed.headless && parsed.animation.is_none() {
+                return Err("--tile-size requires --headless or --animation".into());
+            }
+            if parsed.cpu || parsed.denoise || parsed.checkpoint.is_some() {
+                return Err(
+                    "--tile-size requires the gpu renderer and excludes --denoise and --checkpoint"
+                        .into(),
+                );
+            }
+        }
+        if !parsed.workers.is_empty() {
+            if !parsed.headless && parsed.animation.is_none() {
+                return Err("--workers requires --headless or --animation".into());
+            }
+            if parsed.cpu
+                || parsed.denoise
+                || parsed.checkpoint.is_some()
+                || !parsed.aovs.is_empty()
+            {
+                return Err(
+                    "--workers renders on the gpu of the workers and excludes --denoise, \
+                     --checkpoint and --aovs"
+                        .into(),
+                );
+            }
+        }
+        if parsed.resume && parsed.checkpoint.is_none() {
+            return Err("--resume requires --checkpoint".into());
+        }
+        if parsed.checkpoint.is_some() && (parsed.cpu || parsed.animation.is_some()) {
+            return Err("--checkpoint requires the gpu renderer and a single image".into());
+        }
+        if parsed.watch_shaders && (parsed.headless || parsed.cpu || parsed.animation.is_some()) {
+            return Err("--watch-shaders requires the window".into());
+        }
+        if parsed.denoise && parsed.cpu {
+            return Err("--denoise requires the gpu renderer".into());
+        }
+        if parsed.settings.max_depth == 0 {
+            return Err("--max-depth must be at least 1".into());
+        }
+        let rr_min_probability = parsed.settings.rr_min_probability;
+        if rr_min_probability <= 0.0 || rr_min_probability > 1.0 {
+            return Err("--rr-min-probability must be in (0, 1]".into());
+        }
+        if parsed.settings.adaptive_threshold < 0.0 {
+            return Err("--adaptive-threshold must not be negative".into());
+        }
+
+        Ok(parsed)
+    }
+
+    pub fn output(&self) -> &str {
+        match (&self.output, &self.animation) {
+            (Some(output), _) => output,
+            (None, Some(_)) => "frame_####.png",
+            (None, None) => "render.png",
+        }
+    }
+
+    /// The camera to start from: the selected bookmark, or a camera framing the scene.
+    pub fn initial_camera(
+        &self,
+        bookmarks: &Bookmarks,
+        scene_bounds: &BoundingSphere,
+        size: &PhysicalSize<u32>,
+    ) -> Camera {
+        if let Some(bookmark) = self.bookmark.and_then(|slot| bookmarks.get(slot)) {
+            return bookmark;
+        }
+
+        let mut camera = Camera::new(Vec3::ZERO, 3.0);
+        self.configure_camera(&mut camera);
+        camera.frame(scene_bounds, size);
+        if let Some(far) = self.far {
+            if far < camera.far {
+                log::warn!(
+                    "--far {far} clips the scene, which reaches to {}",
+                    camera.far
+                );
+            }
+            camera.far = far;
+        }
+        camera
+    }
+
+    pub fn configure_camera(&self, camera: &mut Camera) {
+        if let Some(focal_length) = self.focal_length {
+            camera.set_focal_length(focal_length, self.sensor);
+        } else if let Some(fov) = self.fov {
+            camera.set_fov(fov);
+        }
+        // Without --far, the default keeps its ratio to a near distance beyond the default.
+        let far = self
+            .far
+            .unwrap_or(DEFAULT_FAR.max(self.near * DEFAULT_FAR / DEFAULT_NEAR));
+        camera.set_clip_range(self.near, far);
+        self.configure_projection(camera);
+    }
+
+    /// Applies only the projection options, used where the rest of the camera comes from a file.
+    pub fn configure_projection(&self, camera: &mut Camera) {
+        if let Some(projection) = self.projection {
+            camera.projection = projection;
+        }
+        if let Some(eye_separation) = self.eye_separation {
+            camera.eye_separation = eye_separation;
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{flag}'"))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{value}' for '{flag}'"))
+}
+
+/// Parses a number, rejecting the infinities and NaN that `f32::from_str` accepts.
+fn parse_float(flag: &str, value: Option<String>) -> Result<f32, String> {
+    let number: f32 = parse_value(flag, value)?;
+    if !number.is_finite() {
+        return Err(format!("invalid value '{number}' for '{flag}'"));
+    }
+    Ok(number)
+}
+
+fn parse_duration(flag: &str, value: Option<String>) -> Result<Duration, String> {
+    let seconds: f32 = parse_value(flag, value)?;
+    Duration::try_from_secs_f32(seconds).map_err(|err| format!("invalid value for '{flag}': {err}"))
+}
+
+fn parse_size(flag: &str, value: Option<String>) -> Result<PhysicalSize<u32>, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{flag}'"))?;
+    let (width, height) = parse_pair(flag, &value)?;
+    Ok(PhysicalSize::new(width, height))
+}
+
+fn parse_projection(flag: &str, value: Option<String>) -> Result<Projection, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{flag}'"))?;
+    Projection::from_name(&value)
+        .ok_or_else(|| format!("unknown projection '{value}' for '{flag}'"))
+}
+
+fn parse_backend(flag: &str, value: Option<String>) -> Result<wgpu::Backends, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{flag}'"))?;
+    parse_backends(&value).ok_or_else(|| format!("unknown backend '{value}' for '{flag}'"))
+}
+
+fn parse_sampler(flag: &str, value: Option<String>) -> Result<Sampler, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{flag}'"))?;
+    Sampler::from_name(&value).ok_or_else(|| format!("unknown sampler '{value}' for '{flag}'"))
+}
+
+fn parse_aovs(flag: &str, value: Option<String>) -> Result<Vec<Aov>, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{flag}'"))?;
+    if value == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+    value
+        .split(',')
+        .map(|name| {
+            Aov::from_name(name).ok_or_else(|| format!("unknown AOV '{name}' for '{flag}'"))
+        })
+        .collect()
+}
+
+fn parse_sensor(flag: &str, value: Option<String>) -> Result<SensorSize, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{flag}'"))?;
+    if let Some(sensor) = SensorSize::from_name(&value) {
+        return Ok(sensor);
+    }
+
+    let (width, height) = parse_pair(flag, &value)?;
+    Ok(SensorSize::new(width, height))
+}
+
+/// Parses values of the form `WxH`.
+fn parse_pair<T: FromStr>(flag: &str, value: &str) -> Result<(T, T), String> {
+    let (first, second) = value
+        .split_once('x')
+        .ok_or_else(|| format!("invalid value '{value}' for '{flag}', expected WxH"))?;
+    Ok((
+        parse_value(flag, Some(first.to_string()))?,
+        parse_value(flag, Some(second.to_string()))?,
+    ))
+}
+
+/// Seed for runs without `--seed`, varying from run to run.
+fn random_seed() -> u32 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.subsec_nanos() ^ since_epoch.as_secs() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framing_keeps_an_explicit_far_distance() {
+        let parse = |args: &[&str]| Args::parse_from(args.iter().map(|arg| arg.to_string()));
+        let bookmarks = Bookmarks::load(PathBuf::from("missing-bookmarks.toml")).unwrap();
+        let scene_bounds = BoundingSphere {
+            center: Vec3::ZERO,
+            radius: 500.0,
+        };
+        let size = PhysicalSize::new(4, 3);
+
+        let framed = parse(&[])
+            .unwrap()
+            .initial_camera(&bookmarks, &scene_bounds, &size);
+        assert!(framed.far > 500.0);
+        let explicit =
+            parse(&["--far", "50"])
+                .unwrap()
+                .initial_camera(&bookmarks, &scene_bounds, &size);
+        assert_eq!(explicit.far, 50.0);
+
+        let near =
+            parse(&["--near", "150"])
+                .unwrap()
+                .initial_camera(&bookmarks, &scene_bounds, &size);
+        assert!(near.far > near.near);
+        assert!(parse(&["--near", "150", "--far", "100"]).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let parse = |args: &[&str]| Args::parse_from(args.iter().map(|arg| arg.to_string()));
+
+        assert!(parse(&["--fov", "90", "--max-depth", "1"]).is_ok());
+        for args in [
+            &["--fov", "0"][..],
+            &["--fov", "180"],
+            &["--fov", "NaN"],
+            &["--focal-length", "-35"],
+            &["--near", "inf"],
+            &["--sensor", "0x24"],
+            &["--max-depth", "0"],
+            &["--samples", "0"],
+            &["--adaptive-threshold", "-0.1"],
+        ] {
+            assert!(parse(args).is_err(), "{args:?}");
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::{path::Path, time::Instant};
+
+use image::{imageops, Rgba32FImage};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    animation::CameraPath,
+    aov::Aov,
+    args::Args,
+    camera::{Camera, Tile},
+    checkpoint::Checkpoint,
+    distributed::DistributedRenderer,
+    gpu::GpuError,
+    metadata::render_metadata,
+    model::Model,
+    output::{frame_path, save_layers},
+    reference::ReferenceRenderer,
+    renderer::Renderer,
+    skybox::load_skybox_image,
+};
+
+/// Upper bound of the samples per dispatch, which keeps every dispatch well below the gpu
+/// timeout of the operating system.
+const MAX_DISPATCH_SAMPLES: u32 = 16;
+
+/// A renderer that produces finished images without a window.
+trait OfflineRenderer {
+    fn render_image(
+        &mut self,
+        camera: &Camera,
+        num_samples: u32,
+        seed: u32,
+    ) -> Result<Rgba32FImage, String>;
+
+    /// An AOV of the last rendered image, if the renderer writes them.
+    fn read_aov(&self, _aov: Aov) -> Option<Rgba32FImage> {
+        None
+    }
+}
+
+/// Adds samples to the accumulation of a headless renderer until it holds `num_samples`.
+pub fn accumulate(renderer: &mut Renderer, num_samples: u32, seed: u32) {
+    while renderer.num_samples() < num_samples {
+        let dispatch_samples = MAX_DISPATCH_SAMPLES.min(num_samples - renderer.num_samples());
+        renderer
+            .render(seed, dispatch_samples)
+            .expect("headless rendering has no surface to lose");
+    }
+}
+
+impl OfflineRenderer for Renderer {
+    fn render_image(
+        &mut self,
+        camera: &Camera,
+        num_samples: u32,
+        seed: u32,
+    ) -> Result<Rgba32FImage, String> {
+        self.update_camera(camera);
+        accumulate(self, num_samples, seed);
+        Ok(self.read_output())
+    }
+
+    fn read_aov(&self, aov: Aov) -> Option<Rgba32FImage> {
+        Some(self.read_aov(aov))
+    }
+}
+
+impl OfflineRenderer for ReferenceRenderer {
+    fn render_image(
+        &mut self,
+        camera: &Camera,
+        num_samples: u32,
+        seed: u32,
+    ) -> Result<Rgba32FImage, String> {
+        Ok(self.render(camera, num_samples, seed))
+    }
+}
+
+impl OfflineRenderer for DistributedRenderer {
+    fn render_image(
+        &mut self,
+        camera: &Camera,
+        num_samples: u32,
+        seed: u32,
+    ) -> Result<Rgba32FImage, String> {
+        self.render(camera, num_samples, seed)
+    }
+}
+
+/// Renders images in tiles with a renderer of the size of a tile, for images beyond the texture
+/// limits of the gpu, and stitches them together on the cpu.
+struct TiledRenderer {
+    renderer: Renderer,
+    tile_size: PhysicalSize<u32>,
+    image_size: PhysicalSize<u32>,
+    aovs: Vec<(Aov, Rgba32FImage)>,
+}
+
+impl OfflineRenderer for TiledRenderer {
+    fn render_image(
+        &mut self,
+        camera: &Camera,
+        num_samples: u32,
+        seed: u32,
+    ) -> Result<Rgba32FImage, String> {
+        let (width, height) = (self.image_size.width, self.image_size.height);
+        let mut image = Rgba32FImage::new(width, height);
+        for (_, aov_image) in &mut self.aovs {
+            *aov_image = Rgba32FImage::new(width, height);
+        }
+
+        let tiles = Tile::split(self.image_size, self.tile_size);
+        let started = Instant::now();
+        for (index, tile) in tiles.iter().enumerate() {
+            self.renderer.set_tile(Some(*tile));
+            let tile_image = self.renderer.render_image(camera, num_samples, seed)?;
+
+            // Tiles at the edges reach past the image, which replace crops.
+            imageops::replace(&mut image, &tile_image, tile.x.into(), tile.y.into());
+            for (aov, aov_image) in &mut self.aovs {
+                let tile_image = self.renderer.read_aov(*aov);
+                imageops::replace(aov_image, &tile_image, tile.x.into(), tile.y.into());
+            }
+
+            let elapsed = started.elapsed();
+            let remaining = elapsed.mul_f64((tiles.len() - index - 1) as f64 / (index + 1) as f64);
+            log::info!(
+                "Rendered tile {}/{} at ({}, {}) in {:.1?}, {:.0?} remaining",
+                index + 1,
+                tiles.len(),
+                tile.x,
+                tile.y,
+                elapsed,
+                remaining
+            );
+        }
+        Ok(image)
+    }
+
+    fn read_aov(&self, aov: Aov) -> Option<Rgba32FImage> {
+        self.aovs
+            .iter()
+            .find(|(tile_aov, _)| *tile_aov == aov)
+            .map(|(_, image)| image.clone())
+    }
+}
+
+fn create_renderer(args: &Args, model: &Model) -> Result<Box<dyn OfflineRenderer>, String> {
+    let tile_size = args.tile_size.map(|tile_size| {
+        PhysicalSize::new(
+            tile_size.min(args.size.width),
+            tile_size.min(args.size.height),
+        )
+    });
+
+    Ok(if !args.workers.is_empty() {
+        Box::new(DistributedRenderer::new(
+            args.workers.clone(),
+            args.size,
+            tile_size.unwrap_or(args.size),
+            args.sampler,
+            args.settings,
+        ))
+    } else if let Some(tile_size) = tile_size {
+        Box::new(TiledRenderer {
+            renderer: create_gpu_renderer(args, tile_size, model)?,
+            tile_size,
+            image_size: args.size,
+            aovs: args
+                .aovs
+                .iter()
+                .map(|&aov| (aov, Rgba32FImage::default()))
+                .collect(),
+        })
+    } else if args.cpu {
+        let mut renderer = ReferenceRenderer::new(args.size, model, load_skybox_image());
+        renderer.set_sampler(args.sampler);
+        renderer.set_settings(args.settings);
+        Box::new(renderer)
+    } else {
+        Box::new(create_gpu_renderer(args, args.size, model)?)
+    })
+}
+
+fn create_gpu_renderer(
+    args: &Args,
+    size: PhysicalSize<u32>,
+    model: &Model,
+) -> Result<Renderer, String> {
+    let mut renderer = pollster::block_on(Renderer::new_headless(size, model, &args.gpu)).map_err(
+        |err| match err {
+            // Tiled renders exclude --denoise and --checkpoint.
+            GpuError::ImageTooLarge { max_size, .. }
+                if !args.denoise && args.checkpoint.is_none() =>
+            {
+                format!("{err}, render in tiles with --tile-size {max_size} or less")
+            }
+            err => err.to_string(),
+        },
+    )?;
+    renderer.set_sampler(args.sampler);
+    renderer.set_settings(args.settings);
+    renderer.set_denoise(args.denoise);
+    renderer.set_aovs(!args.aovs.is_empty());
+    Ok(renderer)
+}
+
+/// Renders a single image of `camera` to `args.output`, or of the camera of `resume`, continuing
+/// its accumulation.
+pub fn render_still(
+    args: &Args,
+    camera: &Camera,
+    model: &Model,
+    resume: Option<Checkpoint>,
+) -> Result<(), String> {
+    if let Some(path) = &args.checkpoint {
+        return render_checkpointed(args, camera, model, path, resume);
+    }
+
+    let mut renderer = create_renderer(args, model)?;
+    let image = renderer.render_image(camera, args.samples, args.seed)?;
+    save(args, renderer.as_ref(), args.output(), &image, camera)?;
+
+    log::info!("Rendered {}", args.output());
+    Ok(())
+}
+
+/// Renders on the gpu like [`render_still`] and saves the accumulation to `path` every
+/// `args.checkpoint_interval`. The checkpoint is removed once the image is written.
+fn render_checkpointed(
+    args: &Args,
+    camera: &Camera,
+    model: &Model,
+    path: &Path,
+    resume: Option<Checkpoint>,
+) -> Result<(), String> {
+    let mut renderer = create_gpu_renderer(args, args.size, model)?;
+    let camera = resume
+        .as_ref()
+        .map_or(*camera, |checkpoint| checkpoint.camera);
+    renderer.update_camera(&camera);
+    if let Some(checkpoint) = &resume {
+        renderer.restore_accumulation(&checkpoint.state)?;
+        log::info!("Resumed at {} samples", renderer.num_samples());
+    }
+
+    let mut last_checkpoint = Instant::now();
+    loop {
+        // A checkpoint of a finished accumulation still needs a dispatch for the denoiser.
+        let remaining = args.samples.saturating_sub(renderer.num_samples());
+        renderer
+            .render(args.seed, MAX_DISPATCH_SAMPLES.min(remaining))
+            .expect("headless rendering has no surface to lose");
+        if renderer.num_samples() >= args.samples {
+            break;
+        }
+
+        if last_checkpoint.elapsed() >= args.checkpoint_interval {
+            Checkpoint::capture(&renderer, &camera, args.seed, args.sampler, &args.settings)
+                .save(path)?;
+            log::info!("Saved checkpoint at {} samples", renderer.num_samples());
+            last_checkpoint = Instant::now();
+        }
+    }
+
+    let image = renderer.read_output();
+    save(args, &renderer, args.output(), &image, &camera)?;
+    log::info!("Rendered {}", args.output());
+
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(format!("{}: {err}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Renders every frame of the camera path to `args.samples` and writes it to the image
+/// sequence given by `args.output`.
+pub fn render_animation(args: &Args, animation: &Path, model: &Model) -> Result<(), String> {
+    let camera_path = CameraPath::load(animation)?;
+    let num_frames = (camera_path.duration() * args.fps).floor() as usize + 1;
+
+    let mut renderer = create_renderer(args, model)?;
+
+    for frame in 0..num_frames {
+        let mut camera = camera_path.sample(frame as f32 / args.fps);
+        args.configure_projection(&mut camera);
+        let image = renderer.render_image(&camera, args.samples, args.seed)?;
+
+        let output = frame_path(args.output(), frame);
+        save(args, renderer.as_ref(), &output, &image, &camera)?;
+
+        log::info!("Rendered frame {}/{num_frames} to {output}", frame + 1);
+    }
+
+    Ok(())
+}
+
+/// Saves the image and the AOVs requested by `args`.
+fn save(
+    args: &Args,
+    renderer: &dyn OfflineRenderer,
+    output: &str,
+    image: &Rgba32FImage,
+    camera: &Camera,
+) -> Result<(), String> {
+    if let Some(directory) = Path::new(output).parent() {
+        std::fs::create_dir_all(directory).map_err(|err| format!("{output}: {err}"))?;
+    }
+    let aovs = args
+        .aovs
+        .iter()
+        .filter_map(|&aov| Some((aov, renderer.read_aov(aov)?)))
+        .collect::<Vec<_>>();
+    let metadata = render_metadata(camera, args.samples, args.seed, args.sampler);
+    save_layers(
+        Path::new(output),
+        image,
+        &aovs,
+        &args.output_options,
+        &metadata,
+    )
+}
@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+use crate::{camera::Camera, config::parse_config};
+
+pub const NUM_SLOTS: usize = 10;
+
+/// Camera bookmarks, persisted as one `[slot]` table per saved camera.
+pub struct Bookmarks {
+    path: PathBuf,
+    slots: [Option<Camera>; NUM_SLOTS],
+}
+
+impl Bookmarks {
+    /// Loads the bookmarks stored at `path`, a missing file yields empty slots.
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let mut bookmarks = Self {
+            path,
+            slots: [None; NUM_SLOTS],
+        };
+
+        let content = match std::fs::read_to_string(&bookmarks.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(bookmarks),
+            Err(err) => return Err(format!("{}: {err}", bookmarks.path.display())),
+        };
+
+        let sections =
+            parse_config(&content).map_err(|err| format!("{}: {err}", bookmarks.path.display()))?;
+        for section in sections {
+            let slot = section
+                .name
+                .parse::<usize>()
+                .ok()
+                .filter(|slot| *slot < NUM_SLOTS)
+                .ok_or_else(|| {
+                    format!(
+                        "{}: invalid bookmark slot '{}'",
+                        bookmarks.path.display(),
+                        section.name
+                    )
+                })?;
+            bookmarks.slots[slot] = Some(Camera::from_section(&section)?);
+        }
+
+        Ok(bookmarks)
+    }
+
+    pub fn get(&self, slot: usize) -> Option<Camera> {
+        self.slots.get(slot).copied().flatten()
+    }
+
+    /// Stores the camera in the given slot and writes all bookmarks back to disk, next to the
+    /// file first and then replacing it, which keeps the previous bookmarks intact if the
+    /// process ends while writing.
+    pub fn save(&mut self, slot: usize, camera: &Camera) -> Result<(), String> {
+        self.slots[slot] = Some(*camera);
+
+        let content = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, camera)| {
+                camera.map(|camera| camera.write_section(&slot.to_string()))
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let error = |err: std::io::Error| format!("{}: {err}", self.path.display());
+        let temporary_path = self.path.with_extension("tmp");
+        std::fs::write(&temporary_path, content).map_err(error)?;
+        std::fs::rename(&temporary_path, &self.path).map_err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn saved_bookmarks_load_again() {
+        let path = std::env::temp_dir().join("raytracer-bookmarks-test.toml");
+        let mut camera = Camera::new(Vec3::new(1.0, 2.0, 3.0), 4.5);
+        camera.yaw = 0.25;
+
+        let mut bookmarks = Bookmarks::load(path.clone()).unwrap();
+        bookmarks.save(3, &camera).unwrap();
+        let loaded = Bookmarks::load(path.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.get(3), Some(camera));
+        assert_eq!(loaded.get(0), None);
+        assert!(!path.with_extension("tmp").exists());
+    }
+}
@@ -0,0 +1,432 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec2, Vec3};
+
+use crate::model::Vertex;
+
+const NUM_BINS: usize = 16;
+const MAX_LEAF_SIZE: u32 = 2;
+/// Entries of the traversal stack, which bounds the depth of the tree.
+const MAX_DEPTH: usize = 64;
+
+/// Node of a flattened bounding volume hierarchy, the two children of an inner node are stored
+/// next to each other.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct BvhNode {
+    pub min: Vec3,
+    /// Index of the left child for inner nodes, of the first triangle for leaves.
+    pub left_or_first: u32,
+    pub max: Vec3,
+    /// Number of triangles in a leaf, zero for inner nodes and the root of an empty model.
+    pub count: u32,
+}
+
+/// Bounding volume hierarchy over the triangles of a non-indexed vertex list, built with the
+/// surface area heuristic.
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    /// Triangle indices referenced by the leaves.
+    pub triangles: Vec<u32>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3,
+    pub t_min: f32,
+    pub t_max: f32,
+}
+
+/// Closest intersection, shaped like the committed intersection of a wgsl ray query.
+#[derive(Debug, Copy, Clone)]
+pub struct Hit {
+    pub t: f32,
+    pub primitive_index: u32,
+    pub barycentrics: Vec2,
+}
+
+#[derive(Copy, Clone)]
+struct Bounds {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Bounds {
+    const EMPTY: Self = Self {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    fn grow(&mut self, point: Vec3) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    fn merge(&mut self, other: &Bounds) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    fn surface_area(&self) -> f32 {
+        let extent = (self.max - self.min).max(Vec3::ZERO);
+        extent.x * extent.y + extent.y * extent.z + extent.z * extent.x
+    }
+}
+
+impl Bvh {
+    pub fn build(vertices: &[Vertex]) -> Self {
+        Self::build_with_max_depth(vertices, MAX_DEPTH)
+    }
+
+    /// Builds a tree whose leaves are at most `max_depth - 1` levels below the root, which
+    /// keeps the traversal stack of `max_depth` entries from overflowing: every inner node
+    /// pushes both children on top of at most one sibling per level above it.
+    fn build_with_max_depth(vertices: &[Vertex], max_depth: usize) -> Self {
+        let num_triangles = (vertices.len() / 3) as u32;
+        let centroids = (0..num_triangles)
+            .map(|triangle| {
+                triangle_positions(vertices, triangle)
+                    .into_iter()
+                    .sum::<Vec3>()
+                    / 3.0
+            })
+            .collect::<Vec<_>>();
+
+        let mut bvh = Self {
+            nodes: vec![BvhNode {
+                min: Vec3::ZERO,
+                left_or_first: 0,
+                max: Vec3::ZERO,
+                count: num_triangles,
+            }],
+            triangles: (0..num_triangles).collect(),
+        };
+        bvh.update_bounds(0, vertices);
+        bvh.subdivide(0, max_depth - 1, vertices, &centroids);
+
+        bvh
+    }
+
+    fn update_bounds(&mut self, node_index: usize, vertices: &[Vertex]) {
+        let node = &mut self.nodes[node_index];
+        let first = node.left_or_first as usize;
+        let mut bounds = Bounds::EMPTY;
+        for &triangle in &self.triangles[first..first + node.count as usize] {
+            for position in triangle_positions(vertices, triangle) {
+                bounds.grow(position);
+            }
+        }
+        node.min = bounds.min;
+        node.max = bounds.max;
+    }
+
+    /// Splits the node while it is worth it and `levels` more levels fit below it.
+    fn subdivide(
+        &mut self,
+        node_index: usize,
+        levels: usize,
+        vertices: &[Vertex],
+        centroids: &[Vec3],
+    ) {
+        let node = self.nodes[node_index];
+        if node.count <= MAX_LEAF_SIZE || levels == 0 {
+            return;
+        }
+
+        let Some((axis, split_position, split_cost)) =
+            self.find_best_split(&node, vertices, centroids)
+        else {
+            return;
+        };
+        let leaf_cost = node.count as f32
+            * Bounds {
+                min: node.min,
+                max: node.max,
+            }
+            .surface_area();
+        if split_cost >= leaf_cost {
+            return;
+        }
+
+        let first = node.left_or_first as usize;
+        let triangles = &mut self.triangles[first..first + node.count as usize];
+        let mut left_count = 0;
+        for i in 0..triangles.len() {
+            if centroids[triangles[i] as usize][axis] < split_position {
+                triangles.swap(i, left_count);
+                left_count += 1;
+            }
+        }
+        if left_count == 0 || left_count == triangles.len() {
+            return;
+        }
+
+        let left_index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            min: Vec3::ZERO,
+            left_or_first: first as u32,
+            max: Vec3::ZERO,
+            count: left_count as u32,
+        });
+        self.nodes.push(BvhNode {
+            min: Vec3::ZERO,
+            left_or_first: (first + left_count) as u32,
+            max: Vec3::ZERO,
+            count: node.count - left_count as u32,
+        });
+        self.nodes[node_index].left_or_first = left_index as u32;
+        self.nodes[node_index].count = 0;
+
+        for child_index in [left_index, left_index + 1] {
+            self.update_bounds(child_index, vertices);
+            self.subdivide(child_index, levels - 1, vertices, centroids);
+        }
+    }
+
+    /// Bins the triangle centroids along every axis and returns the axis, position and cost of
+    /// the cheapest split.
+    fn find_best_split(
+        &self,
+        node: &BvhNode,
+        vertices: &[Vertex],
+        centroids: &[Vec3],
+    ) -> Option<(usize, f32, f32)> {
+        let first = node.left_or_first as usize;
+        let triangles = &self.triangles[first..first + node.count as usize];
+
+        let mut centroid_bounds = Bounds::EMPTY;
+        for &triangle in triangles {
+            centroid_bounds.grow(centroids[triangle as usize]);
+        }
+
+        let mut best = None;
+        for axis in 0..3 {
+            let (min, max) = (centroid_bounds.min[axis], centroid_bounds.max[axis]);
+            if max <= min {
+                continue;
+            }
+
+            let scale = NUM_BINS as f32 / (max - min);
+            let bin_index =
+                |centroid: Vec3| (((centroid[axis] - min) * scale) as usize).min(NUM_BINS - 1);
+
+            let mut bin_bounds = [Bounds::EMPTY; NUM_BINS];
+            let mut bin_counts = [0u32; NUM_BINS];
+            for &triangle in triangles {
+                let bin = bin_index(centroids[triangle as usize]);
+                bin_counts[bin] += 1;
+                for position in triangle_positions(vertices, triangle) {
+                    bin_bounds[bin].grow(position);
+                }
+            }
+
+            let mut left_areas = [0.0; NUM_BINS - 1];
+            let mut left_counts = [0; NUM_BINS - 1];
+            let mut left_bounds = Bounds::EMPTY;
+            let mut left_count = 0;
+            for split in 0..NUM_BINS - 1 {
+                left_bounds.merge(&bin_bounds[split]);
+                left_count += bin_counts[split];
+                left_areas[split] = left_bounds.surface_area();
+                left_counts[split] = left_count;
+            }
+
+            let mut right_bounds = Bounds::EMPTY;
+            let mut right_count = 0;
+            for split in (0..NUM_BINS - 1).rev() {
+                right_bounds.merge(&bin_bounds[split + 1]);
+                right_count += bin_counts[split + 1];
+
+                let cost = left_counts[split] as f32 * left_areas[split]
+                    + right_count as f32 * right_bounds.surface_area();
+                if left_counts[split] > 0
+                    && right_count > 0
+                    && best.is_none_or(|(_, _, best_cost)| cost < best_cost)
+                {
+                    best = Some((axis, min + (split + 1) as f32 / scale, cost));
+                }
+            }
+        }
+
+        best
+    }
+
+    pub fn intersect(&self, vertices: &[Vertex], ray: &Ray) -> Option<Hit> {
+        self.intersect_with_stack::<MAX_DEPTH>(vertices, ray)
+    }
+
+    /// Traverses a tree built for a maximum depth of `STACK_SIZE`, see
+    /// [`Bvh::build_with_max_depth`].
+    fn intersect_with_stack<const STACK_SIZE: usize>(
+        &self,
+        vertices: &[Vertex],
+        ray: &Ray,
+    ) -> Option<Hit> {
+        let inverse_dir = ray.dir.recip();
+        let mut closest: Option<Hit> = None;
+        let mut t_max = ray.t_max;
+
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_size = 1;
+
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.nodes[stack[stack_size] as usize];
+
+            // Children follow their parent, so only the root of an empty model has neither
+            // triangles nor a left child past index zero, like in the shader.
+            if node.count > 0 || node.left_or_first == 0 {
+                let first = node.left_or_first as usize;
+                for &triangle in &self.triangles[first..first + node.count as usize] {
+                    if let Some(hit) = intersect_triangle(vertices, triangle, ray, t_max) {
+                        t_max = hit.t;
+                        closest = Some(hit);
+                    }
+                }
+                continue;
+            }
+
+            let left = node.left_or_first;
+            let right = left + 1;
+            let left_t = intersect_node(&self.nodes[left as usize], ray, inverse_dir, t_max);
+            let right_t = intersect_node(&self.nodes[right as usize], ray, inverse_dir, t_max);
+
+            // Push the farther child first, so the nearer one is visited next.
+            let mut children = [(left_t, left), (right_t, right)];
+            if left_t < right_t {
+                children.swap(0, 1);
+            }
+            for (t, child) in children {
+                if t.is_finite() {
+                    stack[stack_size] = child;
+                    stack_size += 1;
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+fn triangle_positions(vertices: &[Vertex], triangle: u32) -> [Vec3; 3] {
+    let first = triangle as usize * 3;
+    [
+        vertices[first].position,
+        vertices[first + 1].position,
+        vertices[first + 2].position,
+    ]
+}
+
+/// Slab test, returns the entry distance or infinity on a miss.
+fn intersect_node(node: &BvhNode, ray: &Ray, inverse_dir: Vec3, t_max: f32) -> f32 {
+    let t0 = (node.min - ray.origin) * inverse_dir;
+    let t1 = (node.max - ray.origin) * inverse_dir;
+    let t_enter = t0.min(t1).max_element().max(ray.t_min);
+    let t_exit = t0.max(t1).min_element().min(t_max);
+
+    if t_enter <= t_exit {
+        t_enter
+    } else {
+        f32::INFINITY
+    }
+}
+
+/// Möller-Trumbore intersection, the barycentrics weight the second and third vertex.
+fn intersect_triangle(vertices: &[Vertex], triangle: u32, ray: &Ray, t_max: f32) -> Option<Hit> {
+    let [p0, p1, p2] = triangle_positions(vertices, triangle);
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+
+    let p = ray.dir.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+
+    let s = ray.origin - p0;
+    let u = s.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let v = ray.dir.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inverse_determinant;
+    (t > ray.t_min && t < t_max).then_some(Hit {
+        t,
+        primitive_index: triangle,
+        barycentrics: Vec2::new(u, v),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Depth of the deepest leaf below `node_index`, the root has depth zero.
+    fn depth(bvh: &Bvh, node_index: usize) -> usize {
+        let node = &bvh.nodes[node_index];
+        if node.count > 0 {
+            return 0;
+        }
+        let left = node.left_or_first as usize;
+        1 + depth(bvh, left).max(depth(bvh, left + 1))
+    }
+
+    #[test]
+    fn deep_trees_keep_every_triangle_reachable() {
+        // Exponentially spaced triangles, which the surface area heuristic peels off one by one.
+        let vertex = |position| Vertex {
+            position,
+            _pad0: 0.0,
+            normal: Vec3::Z,
+            material: 0,
+        };
+        let corners = (0..200).map(|index| 1.2f32.powi(index)).collect::<Vec<_>>();
+        let vertices = corners
+            .iter()
+            .flat_map(|&x| {
+                [
+                    vertex(Vec3::new(x, 0.0, 0.0)),
+                    vertex(Vec3::new(x * 1.1, 0.0, 0.0)),
+                    vertex(Vec3::new(x, x * 0.1, 0.0)),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        assert!(depth(&Bvh::build(&vertices), 0) < MAX_DEPTH);
+
+        // Unlimited, the tree would be deeper than the stack of the traversal.
+        const TEST_MAX_DEPTH: usize = 4;
+        let bvh = Bvh::build_with_max_depth(&vertices, TEST_MAX_DEPTH);
+        assert_eq!(depth(&bvh, 0), TEST_MAX_DEPTH - 1);
+
+        for (triangle, &x) in corners.iter().enumerate() {
+            let ray = Ray {
+                origin: Vec3::new(x * 1.02, x * 0.02, 1.0),
+                dir: -Vec3::Z,
+                t_min: 0.0,
+                t_max: f32::INFINITY,
+            };
+            let hit = bvh.intersect_with_stack::<TEST_MAX_DEPTH>(&vertices, &ray);
+            assert_eq!(hit.map(|hit| hit.primitive_index), Some(triangle as u32));
+        }
+    }
+
+    #[test]
+    fn empty_models_have_no_hits() {
+        let bvh = Bvh::build(&[]);
+        let ray = Ray {
+            origin: Vec3::ZERO,
+            dir: Vec3::Z,
+            t_min: 0.0,
+            t_max: f32::INFINITY,
+        };
+        assert!(bvh.intersect(&[], &ray).is_none());
+    }
+}
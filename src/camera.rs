@@ -3,11 +3,122 @@ use std::f32::consts::PI;
 use glam::{Mat4, Vec3};
 use winit::dpi::PhysicalSize;
 
+use crate::{config::Section, model::BoundingSphere};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    /// Full 360x180 degree panorama.
+    Equirectangular,
+    /// Equirectangular panoramas for the left (top) and right (bottom) eye.
+    StereoEquirectangular,
+}
+
+impl Projection {
+    pub const ALL: [Self; 4] = [
+        Self::Perspective,
+        Self::Orthographic,
+        Self::Equirectangular,
+        Self::StereoEquirectangular,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Perspective => "perspective",
+            Self::Orthographic => "orthographic",
+            Self::Equirectangular => "equirectangular",
+            Self::StereoEquirectangular => "stereo-equirectangular",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|projection| projection.name() == name)
+    }
+
+    /// Whether the projection matrix maps the scene onto the image plane, which panoramas map
+    /// in the shader instead.
+    pub fn is_planar(self) -> bool {
+        matches!(self, Self::Perspective | Self::Orthographic)
+    }
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|p| *p == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Camera {
-    target: Vec3,
-    radius: f32,
-    yaw: f32,
-    pitch: f32,
+    pub target: Vec3,
+    pub radius: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+    pub projection: Projection,
+    /// Distance between the eyes of stereo projections.
+    pub eye_separation: f32,
+}
+
+/// A rectangle of pixels of a larger image, which tiled renders render with a sub-frustum of the
+/// camera of the whole image. Tiles at the right and bottom edges may reach past the image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub size: PhysicalSize<u32>,
+    pub image_size: PhysicalSize<u32>,
+}
+
+impl Tile {
+    /// Splits the image into rows of tiles of `size`, from the top left.
+    pub fn split(image_size: PhysicalSize<u32>, size: PhysicalSize<u32>) -> Vec<Self> {
+        (0..image_size.height)
+            .step_by(size.height as usize)
+            .flat_map(|y| {
+                (0..image_size.width)
+                    .step_by(size.width as usize)
+                    .map(move |x| Self {
+                        x,
+                        y,
+                        size,
+                        image_size,
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Physical dimensions of a camera sensor in millimetres.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SensorSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl SensorSize {
+    pub const FULL_FRAME: Self = Self::new(36.0, 24.0);
+    pub const APS_C: Self = Self::new(23.6, 15.6);
+    pub const SUPER_35: Self = Self::new(24.89, 18.66);
+    pub const MICRO_FOUR_THIRDS: Self = Self::new(17.3, 13.0);
+
+    pub const fn new(width: f32, height: f32) -> Self {
+        Self { width, height }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "full-frame" => Some(Self::FULL_FRAME),
+            "aps-c" => Some(Self::APS_C),
+            "super35" => Some(Self::SUPER_35),
+            "micro-four-thirds" => Some(Self::MICRO_FOUR_THIRDS),
+            _ => None,
+        }
+    }
 }
 
 const MOVE_SENSITIVITY: f32 = 0.001;
@@ -15,6 +126,10 @@ const SCROLL_SENSITIVITY: f32 = 0.1;
 const MIN_RADIUS: f32 = 0.1;
 const MIN_PITCH: f32 = -PI / 2.0 + 0.01;
 const MAX_PITCH: f32 = PI / 2.0 - 0.01;
+const DEFAULT_FOV_Y: f32 = 90.0;
+pub const DEFAULT_NEAR: f32 = 0.1;
+pub const DEFAULT_FAR: f32 = 100.0;
+const DEFAULT_EYE_SEPARATION: f32 = 0.065;
 
 impl Camera {
     pub fn new(target: Vec3, radius: f32) -> Self {
@@ -23,6 +138,11 @@ impl Camera {
             radius,
             yaw: 0.0,
             pitch: 0.0,
+            fov_y: DEFAULT_FOV_Y.to_radians(),
+            near: DEFAULT_NEAR,
+            far: DEFAULT_FAR,
+            projection: Projection::Perspective,
+            eye_separation: DEFAULT_EYE_SEPARATION,
         }
     }
 
@@ -32,11 +152,96 @@ impl Camera {
         self.pitch = self.pitch.clamp(MIN_PITCH, MAX_PITCH);
     }
 
-    pub fn zoom(&mut self, delta_radius: f32) {
-        self.radius -= delta_radius * SCROLL_SENSITIVITY;
+    /// Zooms in steps proportional to the size of the scene.
+    pub fn zoom(&mut self, delta_radius: f32, scene_radius: f32) {
+        self.radius -= delta_radius * SCROLL_SENSITIVITY * scene_radius.max(MIN_RADIUS);
         self.radius = self.radius.max(MIN_RADIUS);
     }
 
+    /// Orbits around the center of `sphere` at the distance where it fits into the view, and
+    /// pushes the far clip distance back if it would cut into the sphere.
+    pub fn frame(&mut self, sphere: &BoundingSphere, window_size: &PhysicalSize<u32>) {
+        let aspect_ratio = window_size.width as f32 / window_size.height as f32;
+        let half_fov_y = self.fov_y / 2.0;
+        let half_fov_x = (half_fov_y.tan() * aspect_ratio).atan();
+
+        self.target = sphere.center;
+        self.radius = (sphere.radius / half_fov_y.min(half_fov_x).sin()).max(MIN_RADIUS);
+        self.far = self.far.max(self.radius + sphere.radius);
+    }
+
+    /// Sets the vertical field of view in degrees.
+    pub fn set_fov(&mut self, fov_y: f32) {
+        self.fov_y = fov_y.to_radians();
+    }
+
+    /// Derives the vertical field of view from a lens focal length in millimetres,
+    /// the same way a physical camera with the given sensor would frame the scene.
+    pub fn set_focal_length(&mut self, focal_length: f32, sensor: SensorSize) {
+        self.fov_y = 2.0 * (sensor.height / (2.0 * focal_length)).atan();
+    }
+
+    /// Sets the near and far distances, which bound both the projection and the traced rays.
+    pub fn set_clip_range(&mut self, near: f32, far: f32) {
+        self.near = near;
+        self.far = far;
+    }
+
+    /// Reads a camera from a settings section, see [`Camera::write_section`] for the layout.
+    pub fn from_section(section: &Section) -> Result<Self, String> {
+        let projection = if section.contains("projection") {
+            let name = section.string("projection")?;
+            Projection::from_name(name)
+                .ok_or_else(|| format!("[{}] unknown projection '{name}'", section.name))?
+        } else {
+            Projection::Perspective
+        };
+        let eye_separation = if section.contains("eye_separation") {
+            section.number("eye_separation")?
+        } else {
+            DEFAULT_EYE_SEPARATION
+        };
+
+        Ok(Self {
+            target: section.vec3("target")?,
+            radius: section.number("radius")?,
+            yaw: section.number("yaw")?.to_radians(),
+            pitch: section.number("pitch")?.to_radians(),
+            fov_y: section.number("fov")?.to_radians(),
+            near: section.number("near")?,
+            far: section.number("far")?,
+            projection,
+            eye_separation,
+        })
+    }
+
+    /// Writes the camera as a settings section, with the angles in degrees.
+    pub fn write_section(&self, name: &str) -> String {
+        format!(
+            "[{name}]\n\
+             target = [{}, {}, {}]\n\
+             radius = {}\n\
+             yaw = {}\n\
+             pitch = {}\n\
+             fov = {}\n\
+             near = {}\n\
+             far = {}\n\
+             projection = \"{}\"\n\
+             eye_separation = {}\n",
+            self.target.x,
+            self.target.y,
+            self.target.z,
+            self.radius,
+            self.yaw.to_degrees(),
+            self.pitch.to_degrees(),
+            self.fov_y.to_degrees(),
+            self.near,
+            self.far,
+            self.projection.name(),
+            self.eye_separation,
+        )
+    }
+
     pub fn calculate_view(&self) -> Mat4 {
         let position = self.target
             + Vec3::new(
@@ -48,12 +253,72 @@ impl Camera {
         Mat4::look_at_lh(position, self.target, Vec3::new(0.0, 1.0, 0.0))
     }
 
+    /// Panoramic projections map normalized device coordinates straight to angles in the shader
+    /// and thus use the identity matrix.
     pub fn calculate_projection(&self, window_size: &PhysicalSize<u32>) -> Mat4 {
-        Mat4::perspective_lh(
-            90.0f32.to_radians(),
-            window_size.width as f32 / window_size.height as f32,
-            0.1,
-            100.0,
-        )
+        let aspect_ratio = window_size.width as f32 / window_size.height as f32;
+
+        match self.projection {
+            Projection::Perspective => {
+                Mat4::perspective_lh(self.fov_y, aspect_ratio, self.near, self.far)
+            }
+            Projection::Orthographic => {
+                // Matches the extent of the perspective view at the orbit target.
+                let half_height = self.radius * (self.fov_y / 2.0).tan();
+                let half_width = half_height * aspect_ratio;
+                Mat4::orthographic_lh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.near,
+                    self.far,
+                )
+            }
+            Projection::Equirectangular | Projection::StereoEquirectangular => Mat4::IDENTITY,
+        }
+    }
+
+    /// Off-axis projection of the part of the image `tile` covers, which scales and moves that
+    /// part of the normalized device coordinates of the whole image to the full range.
+    pub fn calculate_tile_projection(&self, tile: &Tile) -> Mat4 {
+        let image_width = tile.image_size.width as f32;
+        let image_height = tile.image_size.height as f32;
+        let left = tile.x as f32 / image_width * 2.0 - 1.0;
+        let right = (tile.x + tile.size.width) as f32 / image_width * 2.0 - 1.0;
+        let top = 1.0 - tile.y as f32 / image_height * 2.0;
+        let bottom = 1.0 - (tile.y + tile.size.height) as f32 / image_height * 2.0;
+
+        Mat4::from_scale(Vec3::new(2.0 / (right - left), 2.0 / (top - bottom), 1.0))
+            * Mat4::from_translation(Vec3::new(-(left + right) / 2.0, -(top + bottom) / 2.0, 0.0))
+            * self.calculate_projection(&tile.image_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_project_to_their_part_of_the_image() {
+        let camera = Camera::new(Vec3::ZERO, 3.0);
+        let image_size = PhysicalSize::new(300, 200);
+        let tiles = Tile::split(image_size, PhysicalSize::new(128, 128));
+        assert_eq!(tiles.len(), 6);
+        assert_eq!((tiles[5].x, tiles[5].y), (256, 128));
+
+        // The corners of the tile at (128, 128)-(256, 256) in the image's coordinates.
+        let image = camera.calculate_projection(&image_size);
+        let tile = camera.calculate_tile_projection(&tiles[4]);
+        let point = image
+            .inverse()
+            .project_point3(Vec3::new(128.0 / 150.0 - 1.0, -0.28, 0.5));
+        let ndc = tile.project_point3(point);
+        assert!((ndc.x - -1.0).abs() < 1e-5 && (ndc.y - 1.0).abs() < 1e-5);
+        let point = image
+            .inverse()
+            .project_point3(Vec3::new(256.0 / 150.0 - 1.0, -1.56, 0.5));
+        let ndc = tile.project_point3(point);
+        assert!((ndc.x - 1.0).abs() < 1e-5 && (ndc.y - -1.0).abs() < 1e-5);
     }
 }
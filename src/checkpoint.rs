@@ -0,0 +1,378 @@
+//! Checkpoints of the gpu accumulation, which let long renders continue after the process
+//! ended.
+//!
+//! The file starts with a magic number and a hash of the scene, image size, sampler, render
+//! settings and camera the accumulated samples depend on. Next come the seed, the sample count,
+//! the image size, the sampler name, the camera as a bookmark section and the contents of the
+//! accumulation textures. Strings are prefixed with their length. As every
+//! random number derives from the seed and the per pixel sample counts of the accumulation,
+//! these restore the sample sequences exactly.
+//!
+//! All numbers are little-endian.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::Camera,
+    config::parse_config,
+    model::{SCENE_MATERIALS, SCENE_MODEL},
+    renderer::{AccumulationState, RenderSettings, Renderer},
+    sampler::Sampler,
+};
+
+const MAGIC: &[u8; 8] = b"RTCKPT02";
+
+pub struct Checkpoint {
+    pub hash: u64,
+    pub seed: u32,
+    pub sampler: Sampler,
+    pub camera: Camera,
+    pub state: AccumulationState,
+}
+
+impl Checkpoint {
+    /// Captures the accumulation of `renderer`, which renders `camera` with the given seed,
+    /// sampler and settings.
+    pub fn capture(
+        renderer: &Renderer,
+        camera: &Camera,
+        seed: u32,
+        sampler: Sampler,
+        settings: &RenderSettings,
+    ) -> Self {
+        let state = renderer.read_accumulation_state();
+        Self {
+            hash: scene_hash(camera, state.size, sampler, settings),
+            seed,
+            sampler,
+            camera: *camera,
+            state,
+        }
+    }
+
+    /// Writes the checkpoint next to `path` first and then replaces `path`, which keeps the
+    /// previous checkpoint intact if the process ends while writing.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let error = |err: std::io::Error| format!("{}: {err}", path.display());
+        let temporary_path = path.with_extension("tmp");
+
+        let mut writer = BufWriter::new(File::create(&temporary_path).map_err(error)?);
+        self.write(&mut writer).map_err(error)?;
+        writer.into_inner().map_err(|err| error(err.into_error()))?;
+
+        std::fs::rename(&temporary_path, path).map_err(error)
+    }
+
+    fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let camera = self.camera.write_section("camera");
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.hash.to_le_bytes())?;
+        for value in [
+            self.seed,
+            self.state.num_samples,
+            self.state.size.width,
+            self.state.size.height,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for string in [self.sampler.name(), &camera] {
+            writer.write_all(&(string.len() as u32).to_le_bytes())?;
+            writer.write_all(string.as_bytes())?;
+        }
+
+        for texture in &self.state.textures {
+            writer.write_all(&(texture.len() as u64).to_le_bytes())?;
+            for value in texture {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        writer.flush()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        Self::parse(&bytes).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /// Loads the checkpoint at `path` if it accumulated `camera` with the same scene and
+    /// configuration, for headless renders that continue the camera of the invocation.
+    pub fn resume(
+        path: &Path,
+        camera: &Camera,
+        size: PhysicalSize<u32>,
+        sampler: Sampler,
+        settings: &RenderSettings,
+    ) -> Result<Self, String> {
+        let checkpoint = Self::load(path)?;
+        checkpoint
+            .validate(scene_hash(camera, size, sampler, settings))
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        Ok(checkpoint)
+    }
+
+    /// Loads the checkpoint at `path` if it accumulated the same scene with `settings`. The
+    /// window continues with the camera, size and sampler stored in the checkpoint, which the
+    /// window may have changed since it started.
+    pub fn resume_window(path: &Path, settings: &RenderSettings) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        let (checkpoint, camera_section) =
+            Self::parse_sections(&bytes).map_err(|err| format!("{}: {err}", path.display()))?;
+        let hash = configuration_hash(
+            camera_section,
+            checkpoint.state.size,
+            checkpoint.sampler,
+            settings,
+        );
+        checkpoint
+            .validate(hash)
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        Ok(checkpoint)
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        Self::parse_sections(bytes).map(|(checkpoint, _)| checkpoint)
+    }
+
+    /// Parses the checkpoint and also returns its camera section as it was written.
+    fn parse_sections(bytes: &[u8]) -> Result<(Self, &str), String> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("not a checkpoint of this renderer version".into());
+        }
+        let hash = u64::from_le_bytes(reader.array()?);
+        let seed = reader.u32()?;
+        let num_samples = reader.u32()?;
+        let size = PhysicalSize::new(reader.u32()?, reader.u32()?);
+
+        let sampler = reader.string()?;
+        let sampler =
+            Sampler::from_name(sampler).ok_or_else(|| format!("unknown sampler '{sampler}'"))?;
+        let camera_section = reader.string()?;
+        let camera = parse_config(camera_section)?
+            .into_iter()
+            .find(|section| section.name == "camera")
+            .ok_or_else(|| "missing camera".to_string())
+            .and_then(|section| Camera::from_section(&section))?;
+
+        let mut textures = Vec::new();
+        while !reader.bytes.is_empty() {
+            let length = u64::from_le_bytes(reader.array()?) as usize;
+            let texture = reader
+                .take(length.checked_mul(4).ok_or("invalid texture size")?)?
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+                .collect();
+            textures.push(texture);
+        }
+
+        let checkpoint = Self {
+            hash,
+            seed,
+            sampler,
+            camera,
+            state: AccumulationState {
+                size,
+                num_samples,
+                textures,
+            },
+        };
+        Ok((checkpoint, camera_section))
+    }
+
+    /// Fails if the checkpoint was rendered with a different scene, size, sampler, settings or
+    /// camera than `hash` describes, see [`scene_hash`].
+    pub fn validate(&self, hash: u64) -> Result<(), String> {
+        if self.hash != hash {
+            return Err(
+                "the checkpoint was rendered with a different scene, camera, --size, --sampler or \
+                 path tracing options"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < length {
+            return Err("truncated checkpoint".into());
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<&'a str, String> {
+        let length = self.u32()? as usize;
+        std::str::from_utf8(self.take(length)?).map_err(|err| err.to_string())
+    }
+}
+
+/// FNV-1a hash of the scene and the configuration the accumulated samples depend on.
+pub fn scene_hash(
+    camera: &Camera,
+    size: PhysicalSize<u32>,
+    sampler: Sampler,
+    settings: &RenderSettings,
+) -> u64 {
+    configuration_hash(&camera.write_section("camera"), size, sampler, settings)
+}
+
+/// Like [`scene_hash`], but for a camera section as it was stored or sent. Hashing the text
+/// avoids comparing against a camera whose angles went through a degree conversion twice.
+pub fn configuration_hash(
+    camera_section: &str,
+    size: PhysicalSize<u32>,
+    sampler: Sampler,
+    settings: &RenderSettings,
+) -> u64 {
+    let configuration = format!(
+        "{camera_section}{}x{}\n{}\n{settings:?}",
+        size.width,
+        size.height,
+        sampler.name()
+    );
+
+    [SCENE_MODEL, SCENE_MATERIALS, &configuration]
+        .iter()
+        .flat_map(|part| part.bytes())
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn checkpoints_round_trip() {
+        let mut camera = Camera::new(Vec3::new(1.0, 2.0, 3.0), 4.5);
+        camera.yaw = 0.1;
+        let size = PhysicalSize::new(2, 1);
+        let checkpoint = Checkpoint {
+            hash: scene_hash(&camera, size, Sampler::Halton, &RenderSettings::default()),
+            seed: 7,
+            sampler: Sampler::Halton,
+            camera,
+            state: AccumulationState {
+                size,
+                num_samples: 64,
+                textures: vec![
+                    vec![0.5, 1.0, -2.0, 64.0, 0.25, 0.0, 1.0, 32.0],
+                    vec![0.1, 0.2],
+                ],
+            },
+        };
+
+        let mut bytes = Vec::new();
+        checkpoint.write(&mut bytes).unwrap();
+        let loaded = Checkpoint::parse(&bytes).unwrap();
+
+        assert_eq!(loaded.camera, camera);
+        assert_eq!(loaded.seed, 7);
+        assert_eq!(loaded.sampler, Sampler::Halton);
+        assert_eq!(loaded.state.num_samples, 64);
+        assert_eq!(loaded.state.textures, checkpoint.state.textures);
+        loaded.validate(checkpoint.hash).unwrap();
+
+        let other_settings = RenderSettings {
+            max_depth: 3,
+            ..RenderSettings::default()
+        };
+        assert!(loaded
+            .validate(scene_hash(&camera, size, Sampler::Halton, &other_settings))
+            .is_err());
+        assert!(Checkpoint::parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn resuming_with_another_camera_fails() {
+        let camera = Camera::new(Vec3::ZERO, 3.0);
+        let size = PhysicalSize::new(1, 1);
+        let settings = RenderSettings::default();
+        let checkpoint = Checkpoint {
+            hash: scene_hash(&camera, size, Sampler::Sobol, &settings),
+            seed: 1,
+            sampler: Sampler::Sobol,
+            camera,
+            state: AccumulationState {
+                size,
+                num_samples: 1,
+                textures: vec![vec![0.5, 0.5, 0.5, 1.0]],
+            },
+        };
+        let path = std::env::temp_dir().join("raytracer-checkpoint-test.ckpt");
+        checkpoint.save(&path).unwrap();
+
+        let resume = |camera| Checkpoint::resume(&path, camera, size, Sampler::Sobol, &settings);
+        assert!(resume(&camera).is_ok());
+        let mut moved = camera;
+        moved.yaw += 0.1;
+        let result = resume(&moved);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn window_resumes_after_orbiting() {
+        // The window started with the default camera, size and sampler, then orbited, toggled
+        // the sampler and saved.
+        let mut camera = Camera::new(Vec3::ZERO, 3.0);
+        camera.update_angles(40.0, -20.0);
+        let size = PhysicalSize::new(3, 2);
+        let settings = RenderSettings::default();
+        let checkpoint = Checkpoint {
+            hash: scene_hash(&camera, size, Sampler::Halton, &settings),
+            seed: 1,
+            sampler: Sampler::Halton,
+            camera,
+            state: AccumulationState {
+                size,
+                num_samples: 1,
+                textures: vec![vec![0.5; 4 * 6]],
+            },
+        };
+        let path = std::env::temp_dir().join("raytracer-window-checkpoint-test.ckpt");
+        checkpoint.save(&path).unwrap();
+
+        let resumed = Checkpoint::resume_window(&path, &settings);
+        let headless = Checkpoint::resume(
+            &path,
+            &Camera::new(Vec3::ZERO, 3.0),
+            size,
+            Sampler::Halton,
+            &settings,
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let resumed = resumed.unwrap();
+        assert_eq!(resumed.camera, camera);
+        assert_eq!(resumed.sampler, Sampler::Halton);
+        assert_eq!(resumed.state.size, size);
+        assert!(headless.is_err());
+    }
+}
@@ -0,0 +1,362 @@
+use std::{fmt, path::Path};
+
+use glam::{Vec3, Vec3Swizzles, Vec4, Vec4Swizzles};
+use image::{Rgb, RgbImage, Rgba32FImage};
+
+const SSIM_WINDOW: usize = 8;
+const SSIM_C1: f32 = 0.01 * 0.01;
+const SSIM_C2: f32 = 0.03 * 0.03;
+/// Standard deviation in pixels of the blur approximating the contrast sensitivity of the eye.
+const FLIP_BLUR_SIGMA: f32 = 1.0;
+/// Largest HyAB distance between two colors of the display gamut (green and blue).
+const FLIP_MAX_COLOR_ERROR: f32 = 308.0;
+const FLIP_COLOR_EXPONENT: f32 = 0.7;
+
+/// Differences between a rendered image and its reference.
+#[derive(Debug, Copy, Clone)]
+pub struct Metrics {
+    /// Root mean square error of the linear rgb values.
+    pub rmse: f32,
+    /// Peak signal to noise ratio in decibels, relative to a peak of 1.0.
+    pub psnr: f32,
+    /// Mean structural similarity of the display luminance, 1.0 for identical images.
+    pub ssim: f32,
+    /// Mean perceptual error in the spirit of NVIDIA's FLIP, 0.0 for identical images.
+    pub flip: f32,
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RMSE: {:.6}, PSNR: {:.2} dB, SSIM: {:.4}, FLIP: {:.4}",
+            self.rmse, self.psnr, self.ssim, self.flip
+        )
+    }
+}
+
+pub fn compare(reference: &Rgba32FImage, image: &Rgba32FImage) -> Result<Metrics, String> {
+    if reference.dimensions() != image.dimensions() {
+        return Err(format!(
+            "image size {:?} does not match the reference size {:?}",
+            image.dimensions(),
+            reference.dimensions()
+        ));
+    }
+
+    let squared_error = reference
+        .pixels()
+        .zip(image.pixels())
+        .map(|(a, b)| rgb(a.0).distance_squared(rgb(b.0)) as f64)
+        .sum::<f64>()
+        / (reference.len() / 4 * 3) as f64;
+    let rmse = squared_error.sqrt() as f32;
+
+    Ok(Metrics {
+        rmse,
+        psnr: -20.0 * rmse.log10(),
+        ssim: ssim(reference, image),
+        flip: mean(&flip_error_map(reference, image)),
+    })
+}
+
+/// Colors every pixel by its perceptual error, from black (none) over red to yellow and white.
+pub fn error_heatmap(reference: &Rgba32FImage, image: &Rgba32FImage) -> RgbImage {
+    let errors = flip_error_map(reference, image);
+    let stops = [
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.5, 0.0, 0.5),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(1.0, 1.0, 1.0),
+    ];
+
+    RgbImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let error = errors[(y * reference.width() + x) as usize].clamp(0.0, 1.0);
+        let position = error * (stops.len() - 1) as f32;
+        let index = (position as usize).min(stops.len() - 2);
+        let color = stops[index].lerp(stops[index + 1], position - index as f32);
+        Rgb((color * 255.0)
+            .round()
+            .as_uvec3()
+            .to_array()
+            .map(|c| c as u8))
+    })
+}
+
+/// Compares two image files, e.g. a render against its reference, and writes the error heatmap
+/// to `heatmap` if given.
+pub fn compare_files(reference: &Path, image: &Path, heatmap: Option<&Path>) -> Result<(), String> {
+    let open = |path: &Path| {
+        image::open(path)
+            .map(|image| image.to_rgba32f())
+            .map_err(|err| format!("{}: {err}", path.display()))
+    };
+    let reference = open(reference)?;
+    let image = open(image)?;
+
+    println!("{}", compare(&reference, &image)?);
+
+    if let Some(heatmap) = heatmap {
+        error_heatmap(&reference, &image)
+            .save(heatmap)
+            .map_err(|err| format!("{}: {err}", heatmap.display()))?;
+    }
+
+    Ok(())
+}
+
+fn rgb(pixel: [f32; 4]) -> Vec3 {
+    Vec4::from(pixel).xyz()
+}
+
+fn display_rgb(pixel: [f32; 4]) -> Vec3 {
+    rgb(pixel).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn mean(values: &[f32]) -> f32 {
+    (values.iter().map(|v| *v as f64).sum::<f64>() / values.len().max(1) as f64) as f32
+}
+
+/// Structural similarity over all `SSIM_WINDOW` sized windows, using summed area tables for the
+/// window statistics.
+fn ssim(reference: &Rgba32FImage, image: &Rgba32FImage) -> f32 {
+    let width = reference.width() as usize;
+    let height = reference.height() as usize;
+    let window = SSIM_WINDOW.min(width).min(height);
+
+    let luminances = |image: &Rgba32FImage| {
+        image
+            .pixels()
+            .map(|pixel| luminance(display_rgb(pixel.0)) as f64)
+            .collect::<Vec<_>>()
+    };
+    let a = luminances(reference);
+    let b = luminances(image);
+
+    let table = |value: &dyn Fn(usize) -> f64| {
+        let mut table = vec![0.0; (width + 1) * (height + 1)];
+        for y in 0..height {
+            for x in 0..width {
+                table[(y + 1) * (width + 1) + x + 1] = value(y * width + x)
+                    + table[y * (width + 1) + x + 1]
+                    + table[(y + 1) * (width + 1) + x]
+                    - table[y * (width + 1) + x];
+            }
+        }
+        table
+    };
+    let sum_a = table(&|i| a[i]);
+    let sum_b = table(&|i| b[i]);
+    let sum_aa = table(&|i| a[i] * a[i]);
+    let sum_bb = table(&|i| b[i] * b[i]);
+    let sum_ab = table(&|i| a[i] * b[i]);
+
+    let window_sum = |table: &[f64], x: usize, y: usize| {
+        let (x1, y1) = (x + window, y + window);
+        table[y1 * (width + 1) + x1] - table[y * (width + 1) + x1] - table[y1 * (width + 1) + x]
+            + table[y * (width + 1) + x]
+    };
+
+    let n = (window * window) as f64;
+    let (c1, c2) = (SSIM_C1 as f64, SSIM_C2 as f64);
+    let mut total = 0.0;
+    let mut count = 0;
+    for y in 0..=height - window {
+        for x in 0..=width - window {
+            let mean_a = window_sum(&sum_a, x, y) / n;
+            let mean_b = window_sum(&sum_b, x, y) / n;
+            let variance_a = window_sum(&sum_aa, x, y) / n - mean_a * mean_a;
+            let variance_b = window_sum(&sum_bb, x, y) / n - mean_b * mean_b;
+            let covariance = window_sum(&sum_ab, x, y) / n - mean_a * mean_b;
+
+            total += ((2.0 * mean_a * mean_b + c1) * (2.0 * covariance + c2))
+                / ((mean_a * mean_a + mean_b * mean_b + c1) * (variance_a + variance_b + c2));
+            count += 1;
+        }
+    }
+
+    (total / count as f64) as f32
+}
+
+/// Per pixel error following the structure of FLIP: a color difference of the blurred images in
+/// a perceptual space, amplified where edges differ.
+fn flip_error_map(reference: &Rgba32FImage, image: &Rgba32FImage) -> Vec<f32> {
+    let width = reference.width() as usize;
+    let height = reference.height() as usize;
+
+    let lab = |image: &Rgba32FImage| {
+        let lab = image
+            .pixels()
+            .map(|pixel| srgb_to_lab(display_rgb(pixel.0)))
+            .collect::<Vec<_>>();
+        blur(&lab, width, height)
+    };
+    let features = |image: &Rgba32FImage| {
+        let luminances = image
+            .pixels()
+            .map(|pixel| luminance(display_rgb(pixel.0)))
+            .collect::<Vec<_>>();
+        edges(&luminances, width, height)
+    };
+
+    let lab_reference = lab(reference);
+    let lab_image = lab(image);
+    let features_reference = features(reference);
+    let features_image = features(image);
+
+    (0..width * height)
+        .map(|i| {
+            let difference = lab_reference[i] - lab_image[i];
+            let hyab = difference.x.abs() + difference.yz().length();
+            let color_error = (hyab / FLIP_MAX_COLOR_ERROR)
+                .clamp(0.0, 1.0)
+                .powf(FLIP_COLOR_EXPONENT);
+            let feature_error =
+                ((features_reference[i] - features_image[i]).abs() / 2f32.sqrt()).clamp(0.0, 1.0);
+
+            color_error.powf(1.0 - feature_error)
+        })
+        .collect()
+}
+
+fn srgb_to_lab(srgb: Vec3) -> Vec3 {
+    let linear = srgb.to_array().map(|c| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    let [r, g, b] = linear;
+
+    // Relative to the D65 white point.
+    let xyz = Vec3::new(
+        (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.9505,
+        0.2126 * r + 0.7152 * g + 0.0722 * b,
+        (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.089,
+    );
+    let f = xyz.to_array().map(|t| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    });
+
+    Vec3::new(
+        116.0 * f[1] - 16.0,
+        500.0 * (f[0] - f[1]),
+        200.0 * (f[1] - f[2]),
+    )
+}
+
+/// Separable gaussian blur with clamped borders.
+fn blur(values: &[Vec3], width: usize, height: usize) -> Vec<Vec3> {
+    let radius = (3.0 * FLIP_BLUR_SIGMA).ceil() as isize;
+    let kernel = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * FLIP_BLUR_SIGMA * FLIP_BLUR_SIGMA)).exp())
+        .collect::<Vec<_>>();
+    let kernel_sum = kernel.iter().sum::<f32>();
+
+    let pass = |values: &[Vec3], horizontal: bool| {
+        let mut result = vec![Vec3::ZERO; values.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Vec3::ZERO;
+                for (weight, offset) in kernel.iter().zip(-radius..=radius) {
+                    let (sx, sy) = if horizontal {
+                        (
+                            (x as isize + offset).clamp(0, width as isize - 1) as usize,
+                            y,
+                        )
+                    } else {
+                        (
+                            x,
+                            (y as isize + offset).clamp(0, height as isize - 1) as usize,
+                        )
+                    };
+                    sum += *weight * values[sy * width + sx];
+                }
+                result[y * width + x] = sum / kernel_sum;
+            }
+        }
+        result
+    };
+
+    pass(&pass(values, true), false)
+}
+
+/// Sobel gradient magnitude of the luminance.
+fn edges(luminances: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let at = |x: isize, y: isize| {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        luminances[y * width + x]
+    };
+
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x - 1, y)
+                - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x, y - 1)
+                - at(x + 1, y - 1);
+            (gx * gx + gy * gy).sqrt() / 4.0
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> Rgba32FImage {
+        Rgba32FImage::from_fn(width, height, |x, y| {
+            Rgba([x as f32 / width as f32, y as f32 / height as f32, 0.5, 1.0])
+        })
+    }
+
+    #[test]
+    fn identical_images_have_no_error() {
+        let image = gradient(32, 24);
+        let metrics = compare(&image, &image).unwrap();
+
+        assert_eq!(metrics.rmse, 0.0);
+        assert!(metrics.psnr.is_infinite());
+        assert!((metrics.ssim - 1.0).abs() < 1e-6);
+        assert_eq!(metrics.flip, 0.0);
+    }
+
+    #[test]
+    fn metrics_grow_with_the_difference() {
+        let reference = gradient(32, 24);
+        let offset = |amount: f32| {
+            let mut image = reference.clone();
+            image.pixels_mut().for_each(|p| p.0[0] += amount);
+            compare(&reference, &image).unwrap()
+        };
+        let small = offset(0.05);
+        let large = offset(0.3);
+
+        assert!(small.rmse < large.rmse);
+        assert!(small.psnr > large.psnr);
+        assert!(small.ssim > large.ssim);
+        assert!(small.flip < large.flip);
+    }
+
+    #[test]
+    fn mismatched_sizes_are_rejected() {
+        assert!(compare(&gradient(8, 8), &gradient(8, 9)).is_err());
+    }
+}
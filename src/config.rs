@@ -0,0 +1,100 @@
+use glam::Vec3;
+
+/// A `[name]` or `[[name]]` table of a TOML-like settings file.
+#[derive(Debug, Default)]
+pub struct Section {
+    pub name: String,
+    pub values: Vec<(String, Value)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f32),
+    Array(Vec<f32>),
+    String(String),
+}
+
+impl Section {
+    fn get(&self, key: &str) -> Result<&Value, String> {
+        self.values
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+            .ok_or_else(|| format!("[{}] is missing '{key}'", self.name))
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.values.iter().any(|(name, _)| name == key)
+    }
+
+    pub fn number(&self, key: &str) -> Result<f32, String> {
+        match self.get(key)? {
+            Value::Number(number) => Ok(*number),
+            _ => Err(format!("[{}] '{key}' must be a number", self.name)),
+        }
+    }
+
+    pub fn string(&self, key: &str) -> Result<&str, String> {
+        match self.get(key)? {
+            Value::String(string) => Ok(string),
+            _ => Err(format!("[{}] '{key}' must be a string", self.name)),
+        }
+    }
+
+    pub fn vec3(&self, key: &str) -> Result<Vec3, String> {
+        match self.get(key)? {
+            Value::Array(values) if values.len() == 3 => Ok(Vec3::from_slice(values)),
+            _ => Err(format!(
+                "[{}] '{key}' must be an array of 3 numbers",
+                self.name
+            )),
+        }
+    }
+}
+
+/// Parses the subset of TOML used by the settings files of the renderer: tables, arrays of
+/// tables, comments and `key = value` pairs holding numbers, number arrays or strings.
+pub fn parse_config(content: &str) -> Result<Vec<Section>, String> {
+    let mut sections = Vec::new();
+    let mut current = Section::default();
+
+    for (line_number, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = |message: &str| format!("line {}: {message}", line_number + 1);
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim_start_matches('[').trim_end_matches(']').trim();
+            sections.push(std::mem::take(&mut current));
+            current.name = name.to_string();
+        } else if let Some((key, value)) = line.split_once('=') {
+            let value = parse_value(value.trim()).ok_or_else(|| error("invalid value"))?;
+            current.values.push((key.trim().to_string(), value));
+        } else {
+            return Err(error("expected '[section]' or 'key = value'"));
+        }
+    }
+    sections.push(current);
+
+    sections.retain(|section| !section.name.is_empty() || !section.values.is_empty());
+    Ok(sections)
+}
+
+fn parse_value(value: &str) -> Option<Value> {
+    if let Some(string) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(Value::String(string.to_string()))
+    } else if let Some(array) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        array
+            .split(',')
+            .map(str::trim)
+            .filter(|element| !element.is_empty())
+            .map(|element| element.parse().ok())
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array)
+    } else {
+        value.parse().ok().map(Value::Number)
+    }
+}
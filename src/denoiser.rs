@@ -0,0 +1,213 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, CommandEncoder, ComputePassDescriptor,
+    ComputePipeline, ComputePipelineDescriptor, Device, Extent3d, PipelineLayoutDescriptor,
+    PushConstantRange, ShaderModuleDescriptor, ShaderSource, ShaderStages, StorageTextureAccess,
+    Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView,
+    TextureViewDescriptor, TextureViewDimension,
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{gpu::render_format_defines, preprocessor::ShaderFiles, renderer::WORKGROUP_SIZE};
+
+const NUM_ITERATIONS: u32 = 5;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct PushConstants {
+    step_size: u32,
+    demodulate: u32,
+}
+
+/// Inputs of the denoiser, written by the path tracer.
+pub struct DenoiserInputs<'a> {
+    pub accumulation: &'a TextureView,
+    pub normal_depth: &'a TextureView,
+    pub albedo: &'a TextureView,
+    pub render: &'a TextureView,
+    pub render_format: TextureFormat,
+}
+
+/// A-Trous wavelet filter of `denoise.wgsl`, guided by the first hit normals, depths and albedos.
+pub struct Denoiser {
+    atrous_pipeline: ComputePipeline,
+    resolve_pipeline: ComputePipeline,
+    /// Reading the accumulation, then alternating between the two intermediate textures.
+    bind_groups: [BindGroup; 3],
+    intermediate_textures: [Texture; 2],
+    size: PhysicalSize<u32>,
+}
+
+impl Denoiser {
+    pub fn new(device: &Device, size: PhysicalSize<u32>, inputs: DenoiserInputs) -> Self {
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("denoise.wgsl"),
+            source: ShaderSource::Wgsl(
+                ShaderFiles::built_in()
+                    .preprocess("denoise.wgsl", render_format_defines(inputs.render_format))
+                    .expect("the built-in shaders compose")
+                    .into(),
+            ),
+        });
+
+        let storage_texture = |binding, access, format| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access,
+                format,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("denoiser"),
+            entries: &[
+                storage_texture(
+                    0,
+                    StorageTextureAccess::ReadOnly,
+                    TextureFormat::Rgba32Float,
+                ),
+                storage_texture(
+                    1,
+                    StorageTextureAccess::WriteOnly,
+                    TextureFormat::Rgba32Float,
+                ),
+                storage_texture(
+                    2,
+                    StorageTextureAccess::ReadOnly,
+                    TextureFormat::Rgba32Float,
+                ),
+                storage_texture(
+                    3,
+                    StorageTextureAccess::ReadOnly,
+                    TextureFormat::Rgba32Float,
+                ),
+                storage_texture(4, StorageTextureAccess::WriteOnly, inputs.render_format),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("denoiser"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStages::COMPUTE,
+                range: 0..(size_of::<PushConstants>() as u32),
+            }],
+        });
+
+        let create_pipeline = |entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        let intermediate_textures = [0, 1].map(|_| {
+            device.create_texture(&TextureDescriptor {
+                label: Some("denoiser texture"),
+                size: Extent3d {
+                    width: size.width,
+                    height: size.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba32Float,
+                usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        });
+        let [ping, pong] = intermediate_textures
+            .each_ref()
+            .map(|texture| texture.create_view(&TextureViewDescriptor::default()));
+
+        let bind_groups = [(inputs.accumulation, &ping), (&ping, &pong), (&pong, &ping)].map(
+            |(input, output)| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("denoiser"),
+                    layout: &bind_group_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(input),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(output),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::TextureView(inputs.normal_depth),
+                        },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: BindingResource::TextureView(inputs.albedo),
+                        },
+                        BindGroupEntry {
+                            binding: 4,
+                            resource: BindingResource::TextureView(inputs.render),
+                        },
+                    ],
+                })
+            },
+        );
+
+        Self {
+            atrous_pipeline: create_pipeline("atrous"),
+            resolve_pipeline: create_pipeline("resolve"),
+            bind_groups,
+            intermediate_textures,
+            size,
+        }
+    }
+
+    /// Filters the accumulation into the render texture and [`Denoiser::output_texture`].
+    pub fn encode(&self, encoder: &mut CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("denoiser"),
+            timestamp_writes: None,
+        });
+        let workgroups_x = self.size.width.div_ceil(WORKGROUP_SIZE);
+        let workgroups_y = self.size.height.div_ceil(WORKGROUP_SIZE);
+
+        compute_pass.set_pipeline(&self.atrous_pipeline);
+        for iteration in 0..NUM_ITERATIONS {
+            compute_pass.set_bind_group(0, &self.bind_groups[bind_group_index(iteration)], &[]);
+            compute_pass.set_push_constants(
+                0,
+                bytemuck::bytes_of(&PushConstants {
+                    step_size: 1 << iteration,
+                    demodulate: (iteration == 0) as u32,
+                }),
+            );
+            compute_pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+        }
+
+        compute_pass.set_pipeline(&self.resolve_pipeline);
+        compute_pass.set_bind_group(0, &self.bind_groups[bind_group_index(NUM_ITERATIONS)], &[]);
+        compute_pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+    }
+
+    /// Linear denoised radiance written by the last [`Denoiser::encode`].
+    pub fn output_texture(&self) -> &Texture {
+        // The resolve pass writes to the texture the last iteration did not write to.
+        &self.intermediate_textures[(NUM_ITERATIONS % 2) as usize]
+    }
+}
+
+/// The first iteration reads the accumulation, the following ones alternate between the
+/// intermediate textures.
+fn bind_group_index(iteration: u32) -> usize {
+    if iteration == 0 {
+        0
+    } else {
+        1 + (iteration as usize - 1) % 2
+    }
+}
@@ -0,0 +1,603 @@
+//! Rendering on several machines: a coordinator splits an image into jobs of tiles and sample
+//! ranges, which worker processes render headless and send back as accumulations with their
+//! per pixel sample counts.
+//!
+//! The coordinator opens a TCP connection to every worker per image and sends a header with
+//! the scene hash, the render configuration and the camera, which the worker confirms with an
+//! empty error message. Every job follows as the tile position and the sample range, answered
+//! by the texels of the tile. Closing the connection ends the image. All numbers are
+//! little-endian, strings are prefixed with their length.
+
+use std::{
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Condvar, Mutex},
+    time::Instant,
+};
+
+use image::{Rgba, Rgba32FImage};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    batch::accumulate,
+    camera::{Camera, Tile},
+    checkpoint::{configuration_hash, scene_hash},
+    config::parse_config,
+    gpu::GpuOptions,
+    model::Model,
+    renderer::{RenderSettings, Renderer},
+    sampler::Sampler,
+};
+
+const MAGIC: &[u8; 8] = b"RTDIST01";
+
+/// Everything a worker needs to render jobs of an image.
+struct Header {
+    hash: u64,
+    seed: u32,
+    image_size: PhysicalSize<u32>,
+    tile_size: PhysicalSize<u32>,
+    sampler: Sampler,
+    settings: RenderSettings,
+    camera: Camera,
+}
+
+/// Samples `first_sample..first_sample + num_samples` of one tile.
+#[derive(Debug, Copy, Clone)]
+struct Job {
+    x: u32,
+    y: u32,
+    first_sample: u32,
+    num_samples: u32,
+}
+
+/// Jobs waiting for a worker, and the number of jobs that workers are rendering.
+struct JobQueue {
+    state: Mutex<(Vec<Job>, usize)>,
+    changed: Condvar,
+}
+
+impl JobQueue {
+    fn new(jobs: Vec<Job>) -> Self {
+        Self {
+            state: Mutex::new((jobs, 0)),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// The next job, waiting while the queue is empty but jobs in progress may still return to
+    /// it. `None` once every job is done.
+    fn take(&self) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let (jobs, in_progress) = &mut *state;
+            if let Some(job) = jobs.pop() {
+                *in_progress += 1;
+                return Some(job);
+            }
+            if *in_progress == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn finish(&self) {
+        self.state.lock().unwrap().1 -= 1;
+        self.changed.notify_all();
+    }
+
+    /// Returns a job taken by a failed worker to the queue.
+    fn retry(&self, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        state.0.push(job);
+        state.1 -= 1;
+        self.changed.notify_all();
+    }
+}
+
+/// Renders images on the workers at the given addresses.
+pub struct DistributedRenderer {
+    workers: Vec<String>,
+    image_size: PhysicalSize<u32>,
+    tile_size: PhysicalSize<u32>,
+    sampler: Sampler,
+    settings: RenderSettings,
+}
+
+impl DistributedRenderer {
+    pub fn new(
+        workers: Vec<String>,
+        image_size: PhysicalSize<u32>,
+        tile_size: PhysicalSize<u32>,
+        sampler: Sampler,
+        settings: RenderSettings,
+    ) -> Self {
+        Self {
+            workers,
+            image_size,
+            tile_size,
+            sampler,
+            settings,
+        }
+    }
+
+    /// Renders `num_samples` samples per pixel, spread over the workers, which take the next
+    /// job whenever they finish one. Jobs of failed workers return to the queue.
+    pub fn render(
+        &self,
+        camera: &Camera,
+        num_samples: u32,
+        seed: u32,
+    ) -> Result<Rgba32FImage, String> {
+        let header = Header {
+            hash: scene_hash(camera, self.image_size, self.sampler, &self.settings),
+            seed,
+            image_size: self.image_size,
+            tile_size: self.tile_size,
+            sampler: self.sampler,
+            settings: self.settings,
+            camera: *camera,
+        };
+
+        // Twice as many jobs as workers, so fast workers take over the share of slow ones.
+        let tiles = Tile::split(self.image_size, self.tile_size);
+        let num_ranges = (2 * self.workers.len())
+            .div_ceil(tiles.len())
+            .clamp(1, num_samples.max(1) as usize) as u32;
+        let range_samples = num_samples.div_ceil(num_ranges);
+        let mut jobs = tiles
+            .iter()
+            .flat_map(|tile| {
+                (0..num_samples)
+                    .step_by(range_samples.max(1) as usize)
+                    .map(|first_sample| Job {
+                        x: tile.x,
+                        y: tile.y,
+                        first_sample,
+                        num_samples: range_samples.min(num_samples - first_sample),
+                    })
+            })
+            .collect::<Vec<_>>();
+        // Workers pop jobs from the back.
+        jobs.reverse();
+        let num_jobs = jobs.len();
+        let queue = JobQueue::new(jobs);
+
+        // Sums of the radiance weighted by the sample counts, and the sample counts.
+        let mut sums = Rgba32FImage::new(self.image_size.width, self.image_size.height);
+        let mut num_finished = 0;
+        let started = Instant::now();
+
+        std::thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            for worker in &self.workers {
+                let (header, queue, sender) = (&header, &queue, sender.clone());
+                scope.spawn(move || {
+                    if let Err(err) = run_jobs(worker, header, queue, &sender) {
+                        log::error!("Worker {worker} failed: {err}");
+                    }
+                });
+            }
+            drop(sender);
+
+            for (worker, job, accumulation) in receiver {
+                merge(&mut sums, &job, self.tile_size.width, &accumulation);
+                num_finished += 1;
+                log::info!(
+                    "Merged job {num_finished}/{num_jobs} from {worker}, tile ({}, {}), \
+                     samples {}..{} after {:.1?}",
+                    job.x,
+                    job.y,
+                    job.first_sample,
+                    job.first_sample + job.num_samples,
+                    started.elapsed()
+                );
+            }
+        });
+
+        if num_finished < num_jobs {
+            return Err(format!(
+                "{} of {num_jobs} jobs are left after every worker stopped",
+                num_jobs - num_finished
+            ));
+        }
+
+        for pixel in sums.pixels_mut() {
+            let [red, green, blue, count] = pixel.0;
+            let weight = if count > 0.0 { 1.0 / count } else { 0.0 };
+            *pixel = Rgba([red * weight, green * weight, blue * weight, 1.0]);
+        }
+        Ok(sums)
+    }
+}
+
+/// Sends jobs from `queue` to `worker` until every job is done, returning the job in progress
+/// to the queue if the worker fails.
+fn run_jobs(
+    worker: &str,
+    header: &Header,
+    queue: &JobQueue,
+    sender: &mpsc::Sender<(String, Job, Vec<f32>)>,
+) -> Result<(), String> {
+    let stream = TcpStream::connect(worker).map_err(|err| err.to_string())?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(|err| err.to_string())?);
+    let mut writer = BufWriter::new(stream);
+
+    write_header(&mut writer, header).map_err(|err| err.to_string())?;
+    writer.flush().map_err(|err| err.to_string())?;
+    let error = read_string(&mut reader).map_err(|err| err.to_string())?;
+    if !error.is_empty() {
+        return Err(error);
+    }
+
+    let num_texels = (header.tile_size.width * header.tile_size.height * 4) as usize;
+    loop {
+        let Some(job) = queue.take() else {
+            return Ok(());
+        };
+
+        let result = (|| {
+            for value in [job.x, job.y, job.first_sample, job.num_samples] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            writer.flush()?;
+            read_f32s(&mut reader, num_texels)
+        })();
+
+        match result {
+            Ok(accumulation) => {
+                sender
+                    .send((worker.to_string(), job, accumulation))
+                    .unwrap();
+                queue.finish();
+            }
+            Err(err) => {
+                queue.retry(job);
+                return Err(err.to_string());
+            }
+        }
+    }
+}
+
+/// Adds the accumulation of a tile to the weighted sums, leaving out the part of edge tiles
+/// that reaches past the image.
+fn merge(sums: &mut Rgba32FImage, job: &Job, tile_width: u32, accumulation: &[f32]) {
+    for (index, texel) in accumulation.chunks_exact(4).enumerate() {
+        let x = job.x + index as u32 % tile_width;
+        let y = job.y + index as u32 / tile_width;
+        if x >= sums.width() || y >= sums.height() {
+            continue;
+        }
+
+        let count = texel[3];
+        let sum = &mut sums.get_pixel_mut(x, y).0;
+        for channel in 0..3 {
+            sum[channel] += texel[channel] * count;
+        }
+        sum[3] += count;
+    }
+}
+
+/// Renders the jobs of coordinators that connect to `address`, one coordinator at a time.
+pub fn run_worker(address: &str, model: &Model, options: &GpuOptions) -> Result<(), String> {
+    let listener = TcpListener::bind(address).map_err(|err| format!("{address}: {err}"))?;
+    log::info!(
+        "Waiting for jobs on {}",
+        listener.local_addr().map_err(|err| err.to_string())?
+    );
+
+    // Kept between images, as long as the tile size stays the same.
+    let mut renderer: Option<(PhysicalSize<u32>, Renderer)> = None;
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(|err| err.to_string())
+            .and_then(|stream| serve(stream, model, options, &mut renderer));
+        if let Err(err) = result {
+            log::error!("Failed to serve a coordinator: {err}");
+        }
+    }
+    Ok(())
+}
+
+fn serve(
+    stream: TcpStream,
+    model: &Model,
+    options: &GpuOptions,
+    renderer: &mut Option<(PhysicalSize<u32>, Renderer)>,
+) -> Result<(), String> {
+    let coordinator = stream.peer_addr().map_err(|err| err.to_string())?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(|err| err.to_string())?);
+    let mut writer = BufWriter::new(stream);
+
+    let header = read_header(&mut reader);
+    write_string(
+        &mut writer,
+        header.as_ref().err().map_or("", String::as_str),
+    )
+    .and_then(|()| writer.flush())
+    .map_err(|err| err.to_string())?;
+    let header = header?;
+    log::info!("Rendering jobs of {coordinator}");
+
+    if renderer
+        .as_ref()
+        .is_none_or(|(size, _)| *size != header.tile_size)
+    {
+        let tile_renderer =
+            pollster::block_on(Renderer::new_headless(header.tile_size, model, options))
+                .map_err(|err| err.to_string())?;
+        *renderer = Some((header.tile_size, tile_renderer));
+    }
+    let (_, renderer) = renderer.as_mut().unwrap();
+    renderer.set_sampler(header.sampler);
+    renderer.set_settings(header.settings);
+
+    loop {
+        let mut values = [0; 4];
+        for value in &mut values {
+            match read_u32(&mut reader) {
+                Ok(read) => *value = read,
+                // The coordinator closes the connection once every job is done.
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+        let [x, y, first_sample, num_samples] = values;
+
+        renderer.set_tile(Some(Tile {
+            x,
+            y,
+            size: header.tile_size,
+            image_size: header.image_size,
+        }));
+        renderer.set_sample_offset(first_sample);
+        renderer.update_camera(&header.camera);
+        accumulate(renderer, num_samples, header.seed);
+
+        let accumulation = renderer.read_accumulation();
+        let result = accumulation
+            .as_raw()
+            .iter()
+            .try_for_each(|value| writer.write_all(&value.to_le_bytes()))
+            .and_then(|()| writer.flush());
+        result.map_err(|err| err.to_string())?;
+    }
+}
+
+fn write_header(writer: &mut impl Write, header: &Header) -> std::io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&header.hash.to_le_bytes())?;
+    for value in [
+        header.seed,
+        header.image_size.width,
+        header.image_size.height,
+        header.tile_size.width,
+        header.tile_size.height,
+        header.settings.max_depth,
+        header.settings.rr_min_depth,
+        header.settings.adaptive_min_samples,
+    ] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    for value in [
+        header.settings.rr_min_probability,
+        header.settings.adaptive_threshold,
+    ] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    write_string(writer, header.sampler.name())?;
+    write_string(writer, &header.camera.write_section("camera"))
+}
+
+/// Reads the header and fails unless it describes the scene of this worker. The hash covers the
+/// camera section as the coordinator sent it.
+fn read_header(reader: &mut impl Read) -> Result<Header, String> {
+    let error = |err: std::io::Error| err.to_string();
+
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic).map_err(error)?;
+    if &magic != MAGIC {
+        return Err("the coordinator runs a different renderer version".into());
+    }
+    let mut hash = [0; 8];
+    reader.read_exact(&mut hash).map_err(error)?;
+
+    let mut values = [0; 8];
+    for value in &mut values {
+        *value = read_u32(reader).map_err(error)?;
+    }
+    let [seed, width, height, tile_width, tile_height, max_depth, rr_min_depth, adaptive_min_samples] =
+        values;
+    let rr_min_probability = f32::from_bits(read_u32(reader).map_err(error)?);
+    let adaptive_threshold = f32::from_bits(read_u32(reader).map_err(error)?);
+
+    let sampler = read_string(reader).map_err(error)?;
+    let sampler =
+        Sampler::from_name(&sampler).ok_or_else(|| format!("unknown sampler '{sampler}'"))?;
+    let camera_section = read_string(reader).map_err(error)?;
+    let camera = parse_config(&camera_section)?
+        .into_iter()
+        .find(|section| section.name == "camera")
+        .ok_or_else(|| "missing camera".to_string())
+        .and_then(|section| Camera::from_section(&section))?;
+
+    let header = Header {
+        hash: u64::from_le_bytes(hash),
+        seed,
+        image_size: PhysicalSize::new(width, height),
+        tile_size: PhysicalSize::new(tile_width, tile_height),
+        sampler,
+        settings: RenderSettings {
+            max_depth,
+            rr_min_depth,
+            rr_min_probability,
+            adaptive_threshold,
+            adaptive_min_samples,
+        },
+        camera,
+    };
+    let hash = configuration_hash(
+        &camera_section,
+        header.image_size,
+        header.sampler,
+        &header.settings,
+    );
+    if hash != header.hash {
+        return Err("the worker has a different scene than the coordinator".into());
+    }
+    Ok(header)
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32s(reader: &mut impl Read, length: usize) -> std::io::Result<Vec<f32>> {
+    let mut bytes = vec![0; length * 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+        .collect())
+}
+
+fn write_string(writer: &mut impl Write, string: &str) -> std::io::Result<()> {
+    writer.write_all(&(string.len() as u32).to_le_bytes())?;
+    writer.write_all(string.as_bytes())
+}
+
+fn read_string(reader: &mut impl Read) -> std::io::Result<String> {
+    let length = read_u32(reader)? as usize;
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use glam::Vec3;
+
+    use super::*;
+
+    /// Serves one coordinator like a worker that renders a constant color, or that drops the
+    /// connection some time after receiving its first job.
+    fn spawn_fake_worker(fails: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = BufWriter::new(stream);
+            let header = read_header(&mut reader).unwrap();
+            write_string(&mut writer, "").unwrap();
+            writer.flush().unwrap();
+
+            let num_texels = header.tile_size.width * header.tile_size.height;
+            while let Ok(job) = (0..4)
+                .map(|_| read_u32(&mut reader))
+                .collect::<Result<Vec<_>, _>>()
+            {
+                // Long enough for the other worker to run out of jobs.
+                std::thread::sleep(Duration::from_millis(if fails { 200 } else { 20 }));
+                if fails {
+                    return;
+                }
+                for _ in 0..num_texels {
+                    for value in [0.5, 0.5, 0.5, job[3] as f32] {
+                        writer.write_all(&f32::to_le_bytes(value)).unwrap();
+                    }
+                }
+                writer.flush().unwrap();
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn jobs_of_failed_workers_go_to_the_others() {
+        let size = PhysicalSize::new(4, 2);
+        let renderer = DistributedRenderer::new(
+            vec![spawn_fake_worker(false), spawn_fake_worker(true)],
+            size,
+            size,
+            Sampler::Sobol,
+            RenderSettings::default(),
+        );
+
+        let image = renderer
+            .render(&Camera::new(Vec3::ZERO, 3.0), 16, 1)
+            .unwrap();
+        assert!(image.pixels().all(|pixel| pixel.0 == [0.5, 0.5, 0.5, 1.0]));
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        let mut camera = Camera::new(Vec3::new(1.0, 2.0, 3.0), 4.5);
+        camera.pitch = 0.2;
+        let image_size = PhysicalSize::new(300, 200);
+        let settings = RenderSettings {
+            adaptive_threshold: 0.0,
+            ..RenderSettings::default()
+        };
+        let header = Header {
+            hash: scene_hash(&camera, image_size, Sampler::Halton, &settings),
+            seed: u32::MAX,
+            image_size,
+            tile_size: PhysicalSize::new(128, 128),
+            sampler: Sampler::Halton,
+            settings,
+            camera,
+        };
+
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, &header).unwrap();
+        let read = read_header(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(read.hash, header.hash);
+        assert_eq!(read.seed, header.seed);
+        assert_eq!(read.image_size, header.image_size);
+        assert_eq!(read.tile_size, header.tile_size);
+        assert_eq!(read.sampler, header.sampler);
+        assert_eq!(read.settings, header.settings);
+        assert_eq!(read.camera, header.camera);
+    }
+
+    #[test]
+    fn merges_weighted_by_sample_counts() {
+        let mut sums = Rgba32FImage::new(3, 1);
+        let job = |x, first_sample| Job {
+            x,
+            y: 0,
+            first_sample,
+            num_samples: 4,
+        };
+        // Two 2x1 tiles, the second one reaching past the image.
+        merge(
+            &mut sums,
+            &job(0, 0),
+            2,
+            &[1.0, 1.0, 1.0, 4.0, 0.5, 0.5, 0.5, 2.0],
+        );
+        merge(
+            &mut sums,
+            &job(0, 4),
+            2,
+            &[0.0, 0.0, 0.0, 4.0, 2.0, 2.0, 2.0, 1.0],
+        );
+        merge(
+            &mut sums,
+            &job(2, 0),
+            2,
+            &[3.0, 3.0, 3.0, 4.0, 9.0, 9.0, 9.0, 4.0],
+        );
+
+        assert_eq!(sums.get_pixel(0, 0).0, [4.0, 4.0, 4.0, 8.0]);
+        assert_eq!(sums.get_pixel(1, 0).0, [3.0, 3.0, 3.0, 3.0]);
+        assert_eq!(sums.get_pixel(2, 0).0, [12.0, 12.0, 12.0, 4.0]);
+    }
+}
@@ -0,0 +1,304 @@
+//! Golden image regression tests of the cpu reference path tracer.
+//!
+//! Every test renders `Suzanne.obj` and compares the result against the reference image in
+//! `assets/golden`, within the tolerances of the test. Failing tests write the render and an
+//! error heatmap to `target/golden`. Run with `UPDATE_GOLDEN=1` to regenerate the references
+//! after an intended change to the shading.
+//!
+//! The scene is lit by a small procedural sky instead of the bundled HDR, which keeps the tests
+//! fast and the references independent of the 4k skybox. A fixed seed makes every render
+//! reproducible.
+
+use std::path::{Path, PathBuf};
+
+use glam::Vec3;
+use image::{Rgba, Rgba32FImage};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::{Camera, Projection, SensorSize},
+    compare::{compare, error_heatmap},
+    model::load_model,
+    output::{save_layers, OutputOptions},
+    reference::ReferenceRenderer,
+    renderer::RenderSettings,
+    sampler::Sampler,
+};
+
+const SIZE: PhysicalSize<u32> = PhysicalSize::new(96, 72);
+const NUM_SAMPLES: u32 = 16;
+const SEED: u32 = 1;
+
+struct Tolerance {
+    max_rmse: f32,
+    min_psnr: f32,
+    min_ssim: f32,
+    max_flip: f32,
+}
+
+const DEFAULT_TOLERANCE: Tolerance = Tolerance {
+    max_rmse: 0.02,
+    min_psnr: 34.0,
+    min_ssim: 0.97,
+    max_flip: 0.02,
+};
+
+/// Bright horizon fading into a blue zenith, with a warm sun for directional shading.
+fn sky() -> Rgba32FImage {
+    let (width, height) = (128, 64);
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        let elevation = 1.0 - y as f32 / height as f32;
+        let sky = Vec3::new(0.9, 0.95, 1.0).lerp(Vec3::new(0.3, 0.5, 0.9), elevation);
+
+        let sun = (x as f32 - 40.0).hypot(y as f32 - 20.0) < 5.0;
+        let color = if sun { Vec3::new(1.0, 0.9, 0.7) } else { sky };
+        Rgba([color.x, color.y, color.z, 1.0])
+    })
+}
+
+fn render(configure: impl FnOnce(&mut Camera)) -> Rgba32FImage {
+    render_with_seed(SEED, configure)
+}
+
+fn render_with_seed(seed: u32, configure: impl FnOnce(&mut Camera)) -> Rgba32FImage {
+    render_with(|_| {}, NUM_SAMPLES, seed, configure)
+}
+
+fn render_with(
+    configure_renderer: impl FnOnce(&mut ReferenceRenderer),
+    num_samples: u32,
+    seed: u32,
+    configure: impl FnOnce(&mut Camera),
+) -> Rgba32FImage {
+    let model = load_model(include_str!("../assets/models/Suzanne.obj"), "");
+    let mut renderer = ReferenceRenderer::new(SIZE, &model, sky());
+    configure_renderer(&mut renderer);
+
+    let mut camera = Camera::new(Vec3::ZERO, 3.0);
+    camera.yaw = 0.5;
+    camera.pitch = 0.3;
+    configure(&mut camera);
+    camera.frame(&model.bounding_sphere().unwrap(), &SIZE);
+
+    renderer.render(&camera, num_samples, seed)
+}
+
+fn save_image(path: &Path, image: &Rgba32FImage) {
+    save_layers(path, image, &[], &OutputOptions::default(), &[]).unwrap();
+}
+
+fn assert_golden(name: &str, image: &Rgba32FImage, tolerance: &Tolerance) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let golden_path = root.join("assets/golden").join(format!("{name}.png"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        save_image(&golden_path, image);
+        return;
+    }
+
+    let golden = image::open(&golden_path)
+        .unwrap_or_else(|err| panic!("{}: {err}", golden_path.display()))
+        .to_rgba32f();
+    let metrics = compare(&golden, image).unwrap();
+
+    let passed = metrics.rmse <= tolerance.max_rmse
+        && metrics.psnr >= tolerance.min_psnr
+        && metrics.ssim >= tolerance.min_ssim
+        && metrics.flip <= tolerance.max_flip;
+    if !passed {
+        let output_directory = root.join("target/golden");
+        std::fs::create_dir_all(&output_directory).unwrap();
+        save_image(&output_directory.join(format!("{name}.png")), image);
+        error_heatmap(&golden, image)
+            .save(output_directory.join(format!("{name}-diff.png")))
+            .unwrap();
+    }
+
+    assert!(
+        passed,
+        "{name} differs from its golden image ({metrics}), see {}",
+        root.join("target/golden").display()
+    );
+}
+
+#[test]
+fn suzanne_perspective() {
+    let image = render(|_| {});
+    assert_golden("suzanne_perspective", &image, &DEFAULT_TOLERANCE);
+}
+
+#[test]
+fn suzanne_telephoto() {
+    let image = render(|camera| camera.set_focal_length(135.0, SensorSize::FULL_FRAME));
+    assert_golden("suzanne_telephoto", &image, &DEFAULT_TOLERANCE);
+}
+
+#[test]
+fn suzanne_orthographic() {
+    let image = render(|camera| camera.projection = Projection::Orthographic);
+    assert_golden("suzanne_orthographic", &image, &DEFAULT_TOLERANCE);
+}
+
+#[test]
+fn suzanne_equirectangular() {
+    let image = render(|camera| camera.projection = Projection::Equirectangular);
+    // Panoramas squeeze the model into few pixels, which makes edges more sensitive to noise.
+    assert_golden(
+        "suzanne_equirectangular",
+        &image,
+        &Tolerance {
+            min_ssim: 0.95,
+            ..DEFAULT_TOLERANCE
+        },
+    );
+}
+
+#[test]
+fn same_seed_is_bit_identical() {
+    let first = render_with_seed(7, |_| {});
+    let second = render_with_seed(7, |_| {});
+    assert_eq!(first.as_raw(), second.as_raw());
+
+    let other_seed = render_with_seed(8, |_| {});
+    assert_ne!(first.as_raw(), other_seed.as_raw());
+}
+
+/// Run with `--nocapture` to print the error of every sampler at the same sample count.
+#[test]
+fn low_discrepancy_renders_have_less_noise() {
+    let converged = render_with(|_| {}, 16 * NUM_SAMPLES, SEED, |_| {});
+    let rmse = |sampler| {
+        let image = render_with(
+            |renderer| renderer.set_sampler(sampler),
+            NUM_SAMPLES,
+            SEED + 1,
+            |_| {},
+        );
+        let metrics = compare(&converged, &image).unwrap();
+        println!("{}: {metrics}", sampler.name());
+        metrics.rmse
+    };
+
+    let blue_noise = rmse(Sampler::BlueNoise);
+    assert!(rmse(Sampler::Sobol) < blue_noise);
+    assert!(rmse(Sampler::Halton) < blue_noise);
+}
+
+#[test]
+fn russian_roulette_is_unbiased() {
+    let render = |settings: RenderSettings| {
+        render_with(
+            |renderer| renderer.set_settings(settings),
+            4 * NUM_SAMPLES,
+            SEED,
+            |_| {},
+        )
+    };
+
+    let settings = RenderSettings::default();
+    let without_roulette = render(RenderSettings {
+        rr_min_depth: settings.max_depth,
+        ..settings
+    });
+    let with_roulette = render(RenderSettings {
+        rr_min_depth: 0,
+        rr_min_probability: 0.5,
+        ..settings
+    });
+
+    // Compares the mean of the per pixel differences against its standard error, which
+    // scales the tolerance with the noise of the sample count.
+    for channel in 0..3 {
+        let differences = with_roulette
+            .pixels()
+            .zip(without_roulette.pixels())
+            .map(|(with, without)| (with.0[channel] - without.0[channel]) as f64)
+            .collect::<Vec<_>>();
+        let num_pixels = differences.len() as f64;
+        let mean = differences.iter().sum::<f64>() / num_pixels;
+        let variance = differences
+            .iter()
+            .map(|difference| (difference - mean).powi(2))
+            .sum::<f64>()
+            / (num_pixels - 1.0);
+        let standard_error = (variance / num_pixels).sqrt();
+
+        assert!(
+            mean.abs() <= 4.0 * standard_error,
+            "channel {channel}: mean difference {mean} exceeds 4 standard errors of {standard_error}"
+        );
+    }
+}
+
+#[test]
+fn adaptive_sampling_stays_close_to_uniform_sampling() {
+    let converged = render_with(|_| {}, 16 * NUM_SAMPLES, SEED, |_| {});
+    let render = |adaptive_threshold| {
+        let model = load_model(include_str!("../assets/models/Suzanne.obj"), "");
+        let mut renderer = ReferenceRenderer::new(SIZE, &model, sky());
+        renderer.set_settings(RenderSettings {
+            adaptive_threshold,
+            adaptive_min_samples: NUM_SAMPLES,
+            ..RenderSettings::default()
+        });
+        let mut camera = Camera::new(Vec3::ZERO, 3.0);
+        camera.yaw = 0.5;
+        camera.pitch = 0.3;
+        camera.frame(&model.bounding_sphere().unwrap(), &SIZE);
+        let (image, sample_counts) =
+            renderer.render_with_sample_counts(&camera, 4 * NUM_SAMPLES, SEED + 1);
+        let metrics = compare(&converged, &image).unwrap();
+        println!("adaptive threshold {adaptive_threshold}: {metrics}");
+        (image, metrics.rmse, sample_counts)
+    };
+
+    // Pixels that stop early keep more noise, but only where it is below the threshold.
+    let (uniform, uniform_rmse, _) = render(0.0);
+    let (_, adaptive_rmse, sample_counts) = render(0.02);
+    assert!(adaptive_rmse < 1.5 * uniform_rmse);
+
+    // Adaptive sampling only ends converged pixels early, the samples they skip are not spent
+    // elsewhere. Pixels that stay noisy with the whole budget must have received all of it.
+    let luminance = |pixel: &Rgba<f32>| pixel.0[..3].iter().sum::<f32>() / 3.0;
+    let mut num_noisy = 0;
+    for ((expected, actual), samples) in
+        converged.pixels().zip(uniform.pixels()).zip(&sample_counts)
+    {
+        let error = (luminance(actual) - luminance(expected)).abs();
+        if error > 0.1 * luminance(expected).max(0.01) {
+            num_noisy += 1;
+            assert_eq!(*samples, 4 * NUM_SAMPLES);
+        }
+    }
+    assert!(num_noisy > 0);
+    assert!(sample_counts
+        .iter()
+        .any(|&samples| samples < 4 * NUM_SAMPLES));
+}
+
+#[test]
+fn bright_sky_keeps_radiance_above_one() {
+    let model = load_model(include_str!("../assets/models/Suzanne.obj"), "");
+    let mut skybox = sky();
+    for pixel in skybox.pixels_mut() {
+        pixel.0[..3].iter_mut().for_each(|channel| *channel *= 4.0);
+    }
+    let renderer = ReferenceRenderer::new(SIZE, &model, skybox);
+    let mut camera = Camera::new(Vec3::ZERO, 3.0);
+    camera.frame(&model.bounding_sphere().unwrap(), &SIZE);
+    let image = renderer.render(&camera, NUM_SAMPLES, SEED);
+
+    // Through the half float exr, which keeps the linear radiance.
+    let path = std::env::temp_dir().join("raytracer-bright-sky-test.exr");
+    let options = OutputOptions {
+        half: true,
+        ..OutputOptions::default()
+    };
+    save_layers(&path, &image, &[], &options, &[]).unwrap();
+    let saved = image::open(&path).unwrap().to_rgba32f();
+    std::fs::remove_file(&path).unwrap();
+
+    let brightest = saved.pixels().map(|pixel| pixel.0[0]).fold(0.0, f32::max);
+    assert!(brightest > 3.0, "brightest pixel {brightest}");
+}
@@ -0,0 +1,327 @@
+use std::fmt;
+
+use wgpu::{
+    Adapter, Backends, CreateSurfaceError, DeviceType, Features, Instance, InstanceDescriptor,
+    Limits, RequestDeviceError, Surface, TextureFormat,
+};
+
+use crate::renderer::PUSH_CONSTANT_SIZE;
+
+/// Features the path tracer cannot run without.
+pub const REQUIRED_FEATURES: Features =
+    Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES.union(Features::PUSH_CONSTANTS);
+
+/// Features of hardware ray tracing. Without them the shader traverses a bvh built on the cpu.
+pub const RAY_QUERY_FEATURES: Features = Features::EXPERIMENTAL_RAY_TRACING_ACCELERATION_STRUCTURE
+    .union(Features::EXPERIMENTAL_RAY_QUERY);
+
+/// Features used if the adapter has them.
+pub const OPTIONAL_FEATURES: Features = RAY_QUERY_FEATURES.union(Features::BGRA8UNORM_STORAGE);
+
+/// Storage textures the path tracer binds to its compute stage.
+const STORAGE_TEXTURES_PER_SHADER_STAGE: u32 = 13;
+
+/// Limits the path tracer cannot run with less than.
+pub fn required_limits() -> Limits {
+    Limits {
+        max_push_constant_size: PUSH_CONSTANT_SIZE,
+        max_storage_textures_per_shader_stage: STORAGE_TEXTURES_PER_SHADER_STAGE,
+        ..Default::default()
+    }
+}
+
+/// Which backends to load and which of their adapters to render with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuOptions {
+    pub backends: Backends,
+    /// Picks the most capable adapter with every required feature if unset, preferring hardware
+    /// ray queries.
+    pub adapter: Option<AdapterChoice>,
+}
+
+impl Default for GpuOptions {
+    fn default() -> Self {
+        Self {
+            backends: Backends::VULKAN,
+            adapter: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdapterChoice {
+    /// Position in the list printed by `--list-adapters`.
+    Index(usize),
+    /// Part of the adapter name, ignoring case.
+    Name(String),
+}
+
+impl AdapterChoice {
+    pub fn parse(value: &str) -> Self {
+        value
+            .parse()
+            .map_or_else(|_| Self::Name(value.to_string()), Self::Index)
+    }
+
+    fn matches(&self, index: usize, adapter: &Adapter) -> bool {
+        match self {
+            Self::Index(choice) => *choice == index,
+            Self::Name(name) => adapter
+                .get_info()
+                .name
+                .to_lowercase()
+                .contains(&name.to_lowercase()),
+        }
+    }
+}
+
+/// Why no renderer could be created on the selected gpu.
+#[derive(Debug)]
+pub enum GpuError {
+    NoAdapter(Backends),
+    UnknownAdapter(AdapterChoice),
+    MissingFeatures {
+        adapter: String,
+        features: Vec<&'static str>,
+    },
+    InsufficientLimits {
+        adapter: String,
+        limits: Vec<String>,
+    },
+    /// The image exceeds the largest texture of the adapter.
+    ImageTooLarge {
+        adapter: String,
+        width: u32,
+        height: u32,
+        max_size: u32,
+    },
+    /// The adapter cannot present to the window.
+    UnsupportedSurface(String),
+    CreateSurface(CreateSurfaceError),
+    RequestDevice(RequestDeviceError),
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoAdapter(backends) => write!(f, "no gpu adapter found for {backends:?}"),
+            Self::UnknownAdapter(AdapterChoice::Index(index)) => {
+                write!(f, "no adapter {index}, see --list-adapters")
+            }
+            Self::UnknownAdapter(AdapterChoice::Name(name)) => {
+                write!(f, "no adapter named '{name}', see --list-adapters")
+            }
+            Self::MissingFeatures { adapter, features } => write!(
+                f,
+                "{adapter} lacks the required features {}, choose another adapter with \
+                 --adapter or --backend, see --list-adapters",
+                features.join(", ")
+            ),
+            Self::InsufficientLimits { adapter, limits } => write!(
+                f,
+                "{adapter} does not reach the required limits {}, choose another adapter with \
+                 --adapter or --backend, see --list-adapters",
+                limits.join(", ")
+            ),
+            Self::ImageTooLarge {
+                adapter,
+                width,
+                height,
+                max_size,
+            } => write!(
+                f,
+                "{width}x{height} exceeds the largest texture of {adapter}, {max_size}x{max_size}"
+            ),
+            Self::UnsupportedSurface(adapter) => {
+                write!(f, "{adapter} cannot present to the window")
+            }
+            Self::CreateSurface(err) => write!(f, "failed to create the window surface: {err}"),
+            Self::RequestDevice(err) => write!(f, "failed to create the gpu device: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+pub fn create_instance(options: &GpuOptions) -> Instance {
+    Instance::new(&InstanceDescriptor {
+        backends: options.backends,
+        ..Default::default()
+    })
+}
+
+/// Names of the required features the adapter lacks.
+fn missing_features(adapter: &Adapter) -> Vec<&'static str> {
+    REQUIRED_FEATURES
+        .difference(adapter.features())
+        .iter_names()
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// The required limits the adapter falls short of, with the supported and the required value.
+fn missing_limits(adapter: &Adapter) -> Vec<String> {
+    let mut limits = Vec::new();
+    required_limits().check_limits_with_fail_fn(
+        &adapter.limits(),
+        false,
+        |name, required, supported| limits.push(format!("{name} {supported} < {required}")),
+    );
+    limits
+}
+
+/// Format of the texture the path tracer renders to. Storage access to the bgra format of the
+/// window surfaces needs a feature.
+pub fn render_format(features: Features) -> TextureFormat {
+    if features.contains(Features::BGRA8UNORM_STORAGE) {
+        TextureFormat::Bgra8Unorm
+    } else {
+        TextureFormat::Rgba8Unorm
+    }
+}
+
+/// Shader defines of the render texture format, see [`render_format`].
+pub fn render_format_defines(format: TextureFormat) -> &'static [&'static str] {
+    match format {
+        TextureFormat::Rgba8Unorm => &["RGBA8_RENDER_TEXTURE"],
+        _ => &[],
+    }
+}
+
+/// Discrete gpus first, cpu emulation last.
+fn device_rank(device_type: DeviceType) -> u32 {
+    match device_type {
+        DeviceType::DiscreteGpu => 0,
+        DeviceType::IntegratedGpu => 1,
+        DeviceType::VirtualGpu => 2,
+        DeviceType::Other => 3,
+        DeviceType::Cpu => 4,
+    }
+}
+
+/// The adapter chosen by `options`, or else the highest ranked adapter that has every required
+/// feature and limit and can present to `surface`.
+pub fn select_adapter(
+    instance: &Instance,
+    options: &GpuOptions,
+    surface: Option<&Surface>,
+) -> Result<Adapter, GpuError> {
+    let mut adapters = instance.enumerate_adapters(options.backends);
+
+    let adapter = if let Some(choice) = &options.adapter {
+        let index = (0..adapters.len())
+            .find(|&index| choice.matches(index, &adapters[index]))
+            .ok_or_else(|| GpuError::UnknownAdapter(choice.clone()))?;
+        adapters.swap_remove(index)
+    } else {
+        // Stable, so adapters of the same type keep their order.
+        adapters.sort_by_key(|adapter| {
+            (
+                !missing_features(adapter).is_empty() || !missing_limits(adapter).is_empty(),
+                !surface.is_none_or(|surface| adapter.is_surface_supported(surface)),
+                !adapter.features().contains(RAY_QUERY_FEATURES),
+                device_rank(adapter.get_info().device_type),
+            )
+        });
+        adapters
+            .into_iter()
+            .next()
+            .ok_or(GpuError::NoAdapter(options.backends))?
+    };
+
+    let name = adapter.get_info().name;
+    let features = missing_features(&adapter);
+    if !features.is_empty() {
+        return Err(GpuError::MissingFeatures {
+            adapter: name,
+            features,
+        });
+    }
+    let limits = missing_limits(&adapter);
+    if !limits.is_empty() {
+        return Err(GpuError::InsufficientLimits {
+            adapter: name,
+            limits,
+        });
+    }
+    if surface.is_some_and(|surface| !adapter.is_surface_supported(surface)) {
+        return Err(GpuError::UnsupportedSurface(name));
+    }
+    Ok(adapter)
+}
+
+/// Lists the adapters of the backends with their indices for `--adapter`, the required features
+/// they lack, the required limits they fall short of and whether they trace rays in hardware.
+pub fn list_adapters(options: &GpuOptions) -> String {
+    let adapters = create_instance(options).enumerate_adapters(options.backends);
+    if adapters.is_empty() {
+        return format!("No adapters found for {:?}\n", options.backends);
+    }
+
+    let mut list = String::new();
+    for (index, adapter) in adapters.iter().enumerate() {
+        let info = adapter.get_info();
+        let features = missing_features(adapter);
+        let limits = missing_limits(adapter);
+        let support = if !features.is_empty() || !limits.is_empty() {
+            let mut missing = Vec::new();
+            if !features.is_empty() {
+                missing.push(format!("missing {}", features.join(", ")));
+            }
+            if !limits.is_empty() {
+                missing.push(format!("below the limits {}", limits.join(", ")));
+            }
+            missing.join("; ")
+        } else if adapter.features().contains(RAY_QUERY_FEATURES) {
+            "all required features and limits, hardware ray queries".to_string()
+        } else {
+            "all required features and limits, bvh traversal without hardware ray queries"
+                .to_string()
+        };
+        let driver = format!("{} {}", info.driver, info.driver_info);
+        list += &format!(
+            "{index}: {} ({}, {:?}, driver {})\n   {support}\n",
+            info.name,
+            info.backend,
+            info.device_type,
+            driver.trim()
+        );
+    }
+    list
+}
+
+/// Parses a comma separated list of backend names.
+pub fn parse_backends(value: &str) -> Option<Backends> {
+    value
+        .split(',')
+        .try_fold(Backends::empty(), |backends, name| {
+            let backend = match name.trim().to_lowercase().as_str() {
+                "vulkan" => Backends::VULKAN,
+                "metal" => Backends::METAL,
+                "dx12" => Backends::DX12,
+                "gl" => Backends::GL,
+                "all" => Backends::all(),
+                _ => return None,
+            };
+            Some(backends | backend)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_backends_and_adapter_choices() {
+        assert_eq!(
+            parse_backends("vulkan, DX12"),
+            Some(Backends::VULKAN | Backends::DX12)
+        );
+        assert_eq!(parse_backends("vulkan,webgpu"), None);
+        assert_eq!(AdapterChoice::parse("1"), AdapterChoice::Index(1));
+        assert_eq!(
+            AdapterChoice::parse("RTX 4090"),
+            AdapterChoice::Name("RTX 4090".into())
+        );
+    }
+}
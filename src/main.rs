@@ -1,37 +1,82 @@
 use std::{
+    path::{Path, PathBuf},
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
+use aov::Aov;
+use args::Args;
+use bookmark::Bookmarks;
 use camera::Camera;
+use checkpoint::Checkpoint;
 use glam::Vec3;
+use metadata::{file_timestamp, render_metadata};
+use model::{load_model, BoundingSphere, Model, SCENE_MATERIALS, SCENE_MODEL};
+use output::{save_layers, OutputOptions};
+use sampler::Sampler;
+use shader_watcher::{ShaderWatcher, POLL_INTERVAL, SHADER_DIRECTORY};
 use winit::{
     application::ApplicationHandler,
-    dpi::PhysicalSize,
-    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::{self, ControlFlow, EventLoop},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
     window::{Window, WindowAttributes},
 };
 
 mod renderer;
 use renderer::*;
 
+mod animation;
+mod aov;
+mod args;
+mod batch;
+mod bookmark;
+mod bvh;
 mod camera;
+mod checkpoint;
+mod compare;
+mod config;
+mod denoiser;
+mod distributed;
+mod gpu;
+mod metadata;
 mod model;
 mod noise;
+mod output;
+mod preprocessor;
+mod reference;
+mod sampler;
+mod shader_watcher;
 mod skybox;
 
+#[cfg(test)]
+mod golden_tests;
+
 struct App {
+    args: Args,
+    bookmarks: Bookmarks,
+    model: Model,
+    scene_bounds: BoundingSphere,
     state: Option<State>,
     counter: FpsCounter,
-    time_since_start: Instant,
+    budget: SampleBudget,
+    /// Checkpoint to continue once the renderer exists.
+    resume: Option<Checkpoint>,
+    last_checkpoint: Instant,
+    /// Reloads the shaders on changes with `--watch-shaders`, once the renderer exists.
+    shader_watcher: Option<ShaderWatcher>,
 }
 
 struct State {
     window: Arc<Window>,
     renderer: Renderer,
     camera: Camera,
+    sampler: Sampler,
+    sample_heatmap: bool,
+    denoise: bool,
+    aov_view: Option<Aov>,
     mouse_drag: MouseDrag,
+    modifiers: ModifiersState,
 }
 
 struct FpsCounter {
@@ -39,6 +84,13 @@ struct FpsCounter {
     num_frames: usize,
 }
 
+/// Ends the accumulation after a number of samples or an amount of time, whichever comes first.
+struct SampleBudget {
+    target_samples: Option<u32>,
+    time_budget: Option<Duration>,
+    started: Instant,
+}
+
 #[derive(Default)]
 struct MouseDrag {
     is_dragging: bool,
@@ -72,13 +124,33 @@ impl FpsCounter {
     }
 }
 
+impl SampleBudget {
+    fn restart(&mut self) {
+        self.started = Instant::now();
+    }
+
+    /// Number of samples per pixel to add in the next frame, zero once the budget is used up.
+    fn dispatch_samples(&self, num_samples: u32, samples_per_dispatch: u32) -> u32 {
+        if self
+            .time_budget
+            .is_some_and(|budget| self.started.elapsed() >= budget)
+        {
+            return 0;
+        }
+
+        self.target_samples.map_or(samples_per_dispatch, |target| {
+            samples_per_dispatch.min(target.saturating_sub(num_samples))
+        })
+    }
+}
+
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &event_loop::ActiveEventLoop) {
         let window = Arc::new(
             event_loop
                 .create_window(
                     WindowAttributes::default()
-                        .with_inner_size(PhysicalSize::new(1920, 1080))
+                        .with_inner_size(self.args.size)
                         .with_resizable(false)
                         .with_title("raytracer"),
                 )
@@ -86,24 +158,68 @@ impl ApplicationHandler for App {
         );
         window.focus_window();
 
-        let window_size = window.inner_size();
-
-        let mut renderer = pollster::block_on(Renderer::new(window.clone()));
-        let camera = Camera::new(Vec3::ZERO, 3.0);
-
-        renderer.update_camera(
-            &camera.calculate_view(),
-            &camera.calculate_projection(&window_size),
+        let mut renderer = exit_on_error(
+            pollster::block_on(Renderer::new(window.clone(), &self.model, &self.args.gpu))
+                .map_err(|err| err.to_string()),
         );
+        let camera = match &self.resume {
+            Some(checkpoint) => checkpoint.camera,
+            None => {
+                self.args
+                    .initial_camera(&self.bookmarks, &self.scene_bounds, &window.inner_size())
+            }
+        };
+
+        renderer.set_temporal_reprojection(self.args.reprojection);
+        renderer.update_camera(&camera);
+        renderer.set_sampler(self.args.sampler);
+        renderer.set_settings(self.args.settings);
+        renderer.set_denoise(self.args.denoise);
+        if let Some(checkpoint) = self.resume.take() {
+            exit_on_error(
+                renderer
+                    .restore_accumulation(&checkpoint.state)
+                    .map_err(|err| format!("failed to resume the checkpoint: {err}")),
+            );
+            log::info!("Resumed at {} samples", renderer.num_samples());
+        }
+        self.last_checkpoint = Instant::now();
+        if self.args.watch_shaders {
+            self.shader_watcher = Some(ShaderWatcher::new(SHADER_DIRECTORY));
+        }
+        window.request_redraw();
 
         self.state = Some(State {
             window,
             renderer,
             camera,
+            sampler: self.args.sampler,
+            sample_heatmap: false,
+            denoise: self.args.denoise,
+            aov_view: None,
             mouse_drag: MouseDrag::default(),
+            modifiers: ModifiersState::default(),
         });
     }
 
+    fn about_to_wait(&mut self, event_loop: &event_loop::ActiveEventLoop) {
+        let (Some(watcher), Some(state)) = (&mut self.shader_watcher, &mut self.state) else {
+            return;
+        };
+        if watcher.poll() {
+            match state.renderer.reload_shaders(watcher.directory()) {
+                Ok(()) => {
+                    log::info!("Reloaded the shaders");
+                    state.window.request_redraw();
+                }
+                Err(err) => {
+                    log::error!("Failed to reload the shaders, keeping the previous ones: {err}")
+                }
+            }
+        }
+        event_loop.set_control_flow(ControlFlow::WaitUntil(Instant::now() + POLL_INTERVAL));
+    }
+
     fn window_event(
         &mut self,
         event_loop: &event_loop::ActiveEventLoop,
@@ -114,22 +230,48 @@ impl ApplicationHandler for App {
             window,
             renderer,
             camera,
+            sampler,
+            sample_heatmap,
+            denoise,
+            aov_view,
             mouse_drag,
+            modifiers,
         }) = &mut self.state
         {
             let mut update_camera = false;
 
             match event {
-                WindowEvent::CloseRequested => event_loop.exit(),
+                WindowEvent::CloseRequested => {
+                    if renderer.num_samples() > 0 {
+                        save_checkpoint(&self.args, renderer, camera, *sampler);
+                    }
+                    event_loop.exit();
+                }
                 WindowEvent::RedrawRequested => {
-                    let num_samples = renderer
-                        .render(self.time_since_start.elapsed().as_secs_f32())
-                        .unwrap();
-                    window.request_redraw();
-
-                    if let Some(fps) = self.counter.get_fps() {
-                        window
-                            .set_title(&format!("raytracer - FPS: {fps}, Samples: {num_samples}"));
+                    if renderer.num_samples() == 0 {
+                        self.budget.restart();
+                    }
+                    let dispatch_samples = self
+                        .budget
+                        .dispatch_samples(renderer.num_samples(), self.args.samples_per_dispatch);
+                    let num_samples = renderer.render(self.args.seed, dispatch_samples).unwrap();
+
+                    if self.last_checkpoint.elapsed() >= self.args.checkpoint_interval {
+                        save_checkpoint(&self.args, renderer, camera, *sampler);
+                        self.last_checkpoint = Instant::now();
+                    }
+
+                    if dispatch_samples > 0 {
+                        window.request_redraw();
+
+                        if let Some(fps) = self.counter.get_fps() {
+                            window.set_title(&format!(
+                                "raytracer - FPS: {fps}, Samples: {num_samples}"
+                            ));
+                        }
+                    } else {
+                        // No further redraws until the accumulation restarts.
+                        window.set_title(&format!("raytracer - Samples: {num_samples}, done"));
                     }
                 }
                 WindowEvent::MouseInput {
@@ -153,34 +295,230 @@ impl ApplicationHandler for App {
                     delta: MouseScrollDelta::LineDelta(_, scroll_y),
                     ..
                 } => {
-                    camera.zoom(scroll_y);
+                    camera.zoom(scroll_y, self.scene_bounds.radius);
                     update_camera = true;
                 }
+                WindowEvent::ModifiersChanged(new_modifiers) => {
+                    *modifiers = new_modifiers.state();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(key_code),
+                            state: ElementState::Pressed,
+                            repeat: false,
+                            ..
+                        },
+                    ..
+                } => match (key_code, bookmark_slot(key_code)) {
+                    (KeyCode::KeyP, _) => {
+                        camera.projection = camera.projection.next();
+                        log::info!("Projection: {}", camera.projection.name());
+                        update_camera = true;
+                    }
+                    (KeyCode::KeyS, _) => {
+                        *sampler = sampler.next();
+                        renderer.set_sampler(*sampler);
+                        window.request_redraw();
+                        log::info!("Sampler: {}", sampler.name());
+                    }
+                    (KeyCode::KeyH, _) => {
+                        *sample_heatmap = !*sample_heatmap;
+                        renderer.set_sample_heatmap(*sample_heatmap);
+                        window.request_redraw();
+                    }
+                    (KeyCode::KeyD, _) => {
+                        *denoise = !*denoise;
+                        renderer.set_denoise(*denoise);
+                        window.request_redraw();
+                        log::info!("Denoiser: {}", if *denoise { "on" } else { "off" });
+                    }
+                    (KeyCode::F12, _) => {
+                        let hdr = !modifiers.shift_key();
+                        match save_screenshot(renderer, camera, *sampler, self.args.seed, hdr) {
+                            Ok(path) => log::info!("Saved screenshot {}", path.display()),
+                            Err(err) => log::error!("Failed to save screenshot: {err}"),
+                        }
+                    }
+                    (KeyCode::KeyV, _) => {
+                        *aov_view = match aov_view {
+                            None => Some(Aov::ALL[0]),
+                            Some(aov) => aov.next(),
+                        };
+                        renderer.set_aov_view(*aov_view);
+                        window.request_redraw();
+                        log::info!("View: {}", aov_view.map_or("beauty", Aov::name));
+                    }
+                    (_, Some(slot)) if modifiers.control_key() => {
+                        match self.bookmarks.save(slot, camera) {
+                            Ok(()) => log::info!("Saved bookmark {slot}"),
+                            Err(err) => log::error!("Failed to save bookmark {slot}: {err}"),
+                        }
+                    }
+                    (_, Some(slot)) => {
+                        if let Some(bookmark) = self.bookmarks.get(slot) {
+                            *camera = bookmark;
+                            update_camera = true;
+                        }
+                    }
+                    _ => {}
+                },
                 _ => {}
             }
 
             if update_camera {
-                let window_size = window.inner_size();
-                renderer.update_camera(
-                    &camera.calculate_view(),
-                    &camera.calculate_projection(&window_size),
-                );
+                renderer.update_camera(camera);
+                window.request_redraw();
             }
         }
     }
 }
 
+/// Saves the linear radiance to an EXR file, or what the window shows to a PNG file, named by
+/// the current time.
+fn save_screenshot(
+    renderer: &Renderer,
+    camera: &Camera,
+    sampler: Sampler,
+    seed: u32,
+    hdr: bool,
+) -> Result<PathBuf, String> {
+    let (image, extension) = if hdr {
+        (renderer.read_output(), "exr")
+    } else {
+        (renderer.read_display(), "png")
+    };
+    let path = PathBuf::from(format!(
+        "screenshot-{}.{extension}",
+        file_timestamp(SystemTime::now())
+    ));
+
+    let metadata = render_metadata(camera, renderer.num_samples(), seed, sampler);
+    save_layers(&path, &image, &[], &OutputOptions::default(), &metadata)?;
+    Ok(path)
+}
+
+/// Saves the accumulation of the window to `--checkpoint`, if given.
+fn save_checkpoint(args: &Args, renderer: &Renderer, camera: &Camera, sampler: Sampler) {
+    let Some(path) = &args.checkpoint else {
+        return;
+    };
+    let checkpoint = Checkpoint::capture(renderer, camera, args.seed, sampler, &args.settings);
+    match checkpoint.save(path) {
+        Ok(()) => log::info!("Saved checkpoint at {} samples", renderer.num_samples()),
+        Err(err) => log::error!("Failed to save the checkpoint: {err}"),
+    }
+}
+
+fn bookmark_slot(key_code: KeyCode) -> Option<usize> {
+    match key_code {
+        KeyCode::Digit0 => Some(0),
+        KeyCode::Digit1 => Some(1),
+        KeyCode::Digit2 => Some(2),
+        KeyCode::Digit3 => Some(3),
+        KeyCode::Digit4 => Some(4),
+        KeyCode::Digit5 => Some(5),
+        KeyCode::Digit6 => Some(6),
+        KeyCode::Digit7 => Some(7),
+        KeyCode::Digit8 => Some(8),
+        KeyCode::Digit9 => Some(9),
+        _ => None,
+    }
+}
+
 fn main() {
     env_logger::init_from_env(env_logger::Env::default().filter_or("RUST_LOG", "wgpu=error,info"));
 
+    let mut args = exit_on_error(Args::parse());
+
+    if let Some((reference, image)) = &args.compare {
+        let heatmap = args.output.as_ref().map(Path::new);
+        exit_on_error(compare::compare_files(reference, image, heatmap));
+        return;
+    }
+
+    if args.list_adapters {
+        print!("{}", gpu::list_adapters(&args.gpu));
+        return;
+    }
+
+    if let Some(address) = &args.worker {
+        let model = load_model(SCENE_MODEL, SCENE_MATERIALS);
+        exit_on_error(distributed::run_worker(address, &model, &args.gpu));
+        return;
+    }
+
+    let model = load_model(SCENE_MODEL, SCENE_MATERIALS);
+    let scene_bounds = model.bounding_sphere().unwrap_or(BoundingSphere {
+        center: Vec3::ZERO,
+        radius: 1.0,
+    });
+
+    let bookmarks = exit_on_error(Bookmarks::load(args.bookmarks.clone()));
+    if let Some(slot) = args.bookmark.filter(|slot| bookmarks.get(*slot).is_none()) {
+        exit_on_error::<()>(Err(format!("bookmark {slot} is not set")));
+    }
+
+    // Headless checkpoints have to continue the camera this invocation renders, the window
+    // continues wherever it was saved.
+    let resume = args
+        .checkpoint
+        .as_ref()
+        .filter(|_| args.resume)
+        .map(|path| {
+            exit_on_error(if args.headless {
+                let camera = args.initial_camera(&bookmarks, &scene_bounds, &args.size);
+                Checkpoint::resume(path, &camera, args.size, args.sampler, &args.settings)
+            } else {
+                Checkpoint::resume_window(path, &args.settings)
+            })
+        });
+    if let Some(checkpoint) = &resume {
+        args.seed = checkpoint.seed;
+        args.size = checkpoint.state.size;
+        args.sampler = checkpoint.sampler;
+    }
+
+    log::info!("Seed: {}", args.seed);
+
+    if let Some(animation) = &args.animation {
+        exit_on_error(batch::render_animation(&args, animation, &model));
+        return;
+    }
+    if args.headless || args.cpu {
+        let camera = args.initial_camera(&bookmarks, &scene_bounds, &args.size);
+        exit_on_error(batch::render_still(&args, &camera, &model, resume));
+        return;
+    }
+
+    let budget = SampleBudget {
+        target_samples: args.target_samples,
+        time_budget: args.time_budget,
+        started: Instant::now(),
+    };
+
     let event_loop = EventLoop::new().unwrap();
 
-    event_loop.set_control_flow(ControlFlow::Poll);
+    event_loop.set_control_flow(ControlFlow::Wait);
     event_loop
         .run_app(&mut App {
+            args,
+            bookmarks,
+            model,
+            scene_bounds,
             state: None,
             counter: FpsCounter::default(),
-            time_since_start: Instant::now(),
+            budget,
+            resume,
+            last_checkpoint: Instant::now(),
+            shader_watcher: None,
         })
         .unwrap();
 }
+
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    })
+}
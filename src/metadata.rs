@@ -0,0 +1,86 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{camera::Camera, model::SCENE_PATH, sampler::Sampler};
+
+/// Keys and values describing how an image was rendered, stored in PNG text chunks and EXR
+/// header attributes. The camera is a bookmark section that can be pasted into a bookmark file.
+pub fn render_metadata(
+    camera: &Camera,
+    num_samples: u32,
+    seed: u32,
+    sampler: Sampler,
+) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "Software",
+            format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        ),
+        ("Creation Time", format_date_time(SystemTime::now())),
+        ("Scene", SCENE_PATH.to_string()),
+        ("Camera", camera.write_section("camera")),
+        ("Samples", num_samples.to_string()),
+        ("Seed", seed.to_string()),
+        ("Sampler", sampler.name().to_string()),
+    ]
+}
+
+/// UTC time in the form `20261019-142301`, which sorts chronologically in file names.
+pub fn file_timestamp(time: SystemTime) -> String {
+    let [year, month, day, hour, minute, second] = utc_date_time(time);
+    format!("{year:04}{month:02}{day:02}-{hour:02}{minute:02}{second:02}")
+}
+
+/// UTC time in the RFC 3339 form `2026-10-19T14:23:01Z`.
+fn format_date_time(time: SystemTime) -> String {
+    let [year, month, day, hour, minute, second] = utc_date_time(time);
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+/// Year, month, day, hour, minute and second, using Howard Hinnant's `civil_from_days`.
+fn utc_date_time(time: SystemTime) -> [u64; 6] {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Days since 0000-03-01, so leap days end every 400, 100 and 4 year cycle.
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+    [
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn formats_utc_dates() {
+        let time = |seconds| UNIX_EPOCH + Duration::from_secs(seconds);
+        assert_eq!(format_date_time(time(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(format_date_time(time(951782400)), "2000-02-29T00:00:00Z");
+        assert_eq!(file_timestamp(time(1792419781)), "20261019-142301");
+    }
+}
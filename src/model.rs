@@ -1,9 +1,105 @@
 use bytemuck::{Pod, Zeroable};
 use glam::Vec3;
 
-#[derive(Default, Debug)]
+/// Path of the scene model relative to the repository, for the metadata of rendered images.
+pub const SCENE_PATH: &str = "assets/models/E30_Final01.obj";
+pub const SCENE_MODEL: &str = include_str!("../assets/models/E30_Final01.obj");
+pub const SCENE_MATERIALS: &str = include_str!("../assets/models/E30_Final01.mtl");
+
+pub const MATERIAL_DIFFUSE: u32 = 0;
+pub const MATERIAL_MIRROR: u32 = 1;
+
+/// Albedo of faces without a material or with a material missing from the .mtl file.
+const DEFAULT_ALBEDO: Vec3 = Vec3::splat(0.5);
+
+/// Materials rendered as perfect mirrors instead of diffuse surfaces.
+const MIRROR_MATERIALS: [&str; 12] = [
+    "BMW_E30_M3_WINDOWS",
+    "BMW_E30_M3_CHROME",
+    "BMW_E30_M3_LENS",
+    "BMW_E30_M3_SIDE_MIRROR",
+    "BMW_E30_M3_RIM",
+    "BMW_E30_M3_EMBLEMS",
+    "BMW_E30_M3_HEADLIGHT_REFLECTOR",
+    "BMW_E30_M3_TAILLIGHT_REFLECTOR",
+    "BMW_E30_M3_PLASTIC",
+    "Brake_Disc",
+    "Brembo_Calipers",
+    "Logo_Plane",
+];
+
+#[derive(Debug)]
 pub struct Model {
     pub vertices: Vec<Vertex>,
+    /// Materials referenced by [`Vertex::material`], the first one is the default material.
+    pub materials: Vec<Material>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Aabb {
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+}
+
+impl Model {
+    /// Returns `None` for models without any vertices.
+    pub fn bounds(&self) -> Option<Aabb> {
+        let first = self.vertices.first()?.position;
+        Some(self.vertices.iter().fold(
+            Aabb {
+                min: first,
+                max: first,
+            },
+            |aabb, vertex| Aabb {
+                min: aabb.min.min(vertex.position),
+                max: aabb.max.max(vertex.position),
+            },
+        ))
+    }
+
+    /// Sphere around the center of the bounding box that encloses every vertex.
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        let center = self.bounds()?.center();
+        let radius = self
+            .vertices
+            .iter()
+            .map(|vertex| vertex.position.distance(center))
+            .fold(0.0, f32::max);
+
+        Some(BoundingSphere { center, radius })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct Material {
+    /// Diffuse reflectance, the `Kd` of the .mtl file.
+    pub albedo: Vec3,
+    /// [`MATERIAL_DIFFUSE`] or [`MATERIAL_MIRROR`].
+    pub kind: u32,
+}
+
+impl Material {
+    fn new(name: &str, albedo: Vec3) -> Self {
+        let kind = if MIRROR_MATERIALS.contains(&name) {
+            MATERIAL_MIRROR
+        } else {
+            MATERIAL_DIFFUSE
+        };
+        Self { albedo, kind }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
@@ -26,8 +122,18 @@ impl Vertex {
     }
 }
 
-pub fn load_model(model_content: &str) -> Model {
-    let mut model = Model::default();
+/// Loads an .obj model, with the materials it uses from the contents of its .mtl file.
+pub fn load_model(model_content: &str, materials_content: &str) -> Model {
+    let material_names = parse_materials(materials_content);
+    let mut model = Model {
+        vertices: Vec::new(),
+        materials: vec![Material::new("", DEFAULT_ALBEDO)],
+    };
+    model.materials.extend(
+        material_names
+            .iter()
+            .map(|(name, albedo)| Material::new(name, *albedo)),
+    );
 
     let mut temp_vertices = Vec::new();
     let mut temp_normals = Vec::new();
@@ -47,21 +153,10 @@ pub fn load_model(model_content: &str) -> Model {
                 n2.parse().unwrap(),
             )),
             ["usemtl", material_name] => {
-                temp_material_num = match *material_name {
-                    "BMW_E30_M3_WINDOWS" => 1,
-                    "BMW_E30_M3_CHROME" => 1,
-                    "BMW_E30_M3_LENS" => 1,
-                    "BMW_E30_M3_SIDE_MIRROR" => 1,
-                    "BMW_E30_M3_RIM" => 1,
-                    "BMW_E30_M3_EMBLEMS" => 1,
-                    "BMW_E30_M3_HEADLIGHT_REFLECTOR" => 1,
-                    "BMW_E30_M3_TAILLIGHT_REFLECTOR" => 1,
-                    "BMW_E30_M3_PLASTIC" => 1,
-                    "Brake_Disc" => 1,
-                    "Brembo_Calipers" => 1,
-                    "Logo_Plane" => 1,
-                    _ => 0,
-                };
+                temp_material_num = material_names
+                    .iter()
+                    .position(|(name, _)| name == material_name)
+                    .map_or(0, |index| index as u32 + 1);
             }
             ["f", f0, f1, f2] => {
                 let indices0 = parse_indices(f0);
@@ -129,6 +224,24 @@ pub fn load_model(model_content: &str) -> Model {
     model
 }
 
+/// Name and diffuse color of every material in an .mtl file.
+fn parse_materials(content: &str) -> Vec<(String, Vec3)> {
+    let mut materials = Vec::<(String, Vec3)>::new();
+
+    for line in content.lines() {
+        let values = line.split_whitespace().collect::<Vec<_>>();
+        match (values.as_slice(), materials.last_mut()) {
+            (["newmtl", name], _) => materials.push((name.to_string(), DEFAULT_ALBEDO)),
+            (["Kd", r, g, b], Some((_, albedo))) => {
+                *albedo = Vec3::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap())
+            }
+            _ => {}
+        }
+    }
+
+    materials
+}
+
 fn parse_indices(index: &str) -> (usize, usize) {
     let values = index.split("/").collect::<Vec<_>>();
     (
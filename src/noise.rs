@@ -1,32 +1,43 @@
 use std::io::Cursor;
 
-use image::{EncodableLayout, ImageFormat, ImageReader};
+use image::{EncodableLayout, ImageFormat, ImageReader, Rgba32FImage};
 use wgpu::{
     util::{DeviceExt, TextureDataOrder},
     Device, Extent3d, Queue, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
     TextureView, TextureViewDescriptor, TextureViewDimension,
 };
 
-pub fn load_noise(queue: &Queue, device: &Device) -> TextureView {
-    let noise_images = [
+pub fn load_noise_images() -> Vec<Rgba32FImage> {
+    let noise_images: [&[u8]; 8] = [
         include_bytes!("../assets/blue_noise/HDR_RGBA_0.png"),
         include_bytes!("../assets/blue_noise/HDR_RGBA_1.png"),
         include_bytes!("../assets/blue_noise/HDR_RGBA_2.png"),
+        include_bytes!("../assets/blue_noise/HDR_RGBA_3.png"),
         include_bytes!("../assets/blue_noise/HDR_RGBA_4.png"),
         include_bytes!("../assets/blue_noise/HDR_RGBA_5.png"),
         include_bytes!("../assets/blue_noise/HDR_RGBA_6.png"),
         include_bytes!("../assets/blue_noise/HDR_RGBA_7.png"),
     ];
 
+    noise_images
+        .iter()
+        .map(|noise_image| {
+            let mut reader = ImageReader::new(Cursor::new(noise_image));
+            reader.set_format(ImageFormat::Png);
+
+            let decoded = reader.decode().unwrap();
+            decoded.to_rgba32f()
+        })
+        .collect()
+}
+
+pub fn load_noise(queue: &Queue, device: &Device) -> TextureView {
+    let noise_images = load_noise_images();
+
     let mut noise_buffer = Vec::new();
 
     for noise_image in &noise_images {
-        let mut reader = ImageReader::new(Cursor::new(noise_image));
-        reader.set_format(ImageFormat::Png);
-
-        let decoded = reader.decode().unwrap();
-        let formmatted = decoded.to_rgba32f();
-        noise_buffer.extend_from_slice(formmatted.as_bytes());
+        noise_buffer.extend_from_slice(noise_image.as_bytes());
     }
 
     let noise_texture = device.create_texture_with_data(
@@ -0,0 +1,266 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use exr::prelude::{
+    f16, AnyChannel, AnyChannels, AttributeValue, Encoding, FlatSamples, Image, Layer,
+    LayerAttributes, SmallVec, Text, WritableImage,
+};
+use image::{DynamicImage, ImageBuffer, Pixel, Primitive, Rgba, Rgba32FImage};
+
+use crate::aov::Aov;
+
+/// Encoding choices the file extension leaves open.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct OutputOptions {
+    /// Stores the color channels of EXR files as half floats. Depth, position and IDs keep full
+    /// precision.
+    pub half: bool,
+    /// Writes 16 instead of 8 bits per channel to PNG files.
+    pub png_16_bit: bool,
+}
+
+/// Saves the beauty pass and its AOVs, selecting the format by the extension of `path`.
+///
+/// `.exr` files keep the linear radiance and store the AOVs as named channels of the same file,
+/// e.g. `albedo.R`. `.pfm` files keep the linear radiance without alpha. Every other format
+/// receives the clamped values the window displays, 8 bits per channel unless
+/// [`OutputOptions::png_16_bit`] is set, and writes every AOV to a file of its own, see
+/// [`aov_path`]. The `metadata` goes into the header attributes of `.exr` files and the text
+/// chunks of `.png` files.
+pub fn save_layers(
+    path: &Path,
+    beauty: &Rgba32FImage,
+    aovs: &[(Aov, Rgba32FImage)],
+    options: &OutputOptions,
+    metadata: &[(&str, String)],
+) -> Result<(), String> {
+    let has_extension = |expected: &str| {
+        path.extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(expected))
+    };
+
+    if has_extension("exr") {
+        return save_exr(path, beauty, aovs, options, metadata)
+            .map_err(|err| format!("{}: {err}", path.display()));
+    }
+
+    for (path, image) in std::iter::once((path.to_path_buf(), beauty)).chain(
+        aovs.iter()
+            .map(|(aov, image)| (aov_path(path, *aov), image)),
+    ) {
+        let result = if has_extension("pfm") {
+            save_pfm(&path, image).map_err(|err| err.to_string())
+        } else if has_extension("png") {
+            save_png(&path, image, options.png_16_bit, metadata).map_err(|err| err.to_string())
+        } else if has_extension("jpg") || has_extension("jpeg") {
+            // JPEG has no alpha channel.
+            DynamicImage::ImageRgba8(to_display(image))
+                .to_rgb8()
+                .save(&path)
+                .map_err(|err| err.to_string())
+        } else {
+            DynamicImage::ImageRgba8(to_display(image))
+                .save(&path)
+                .map_err(|err| err.to_string())
+        };
+        result.map_err(|err| format!("{}: {err}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Path of an AOV next to the beauty pass at `path`, e.g. `render.albedo.png` for
+/// `render.png`.
+pub fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{stem}.{}.{}", aov.name(), extension.to_string_lossy()),
+        None => format!("{stem}.{}", aov.name()),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Clamps the linear values to the displayable range and quantizes them to `u8` or `u16`.
+fn to_display<T: Primitive>(image: &Rgba32FImage) -> ImageBuffer<Rgba<T>, Vec<T>>
+where
+    Rgba<T>: Pixel<Subpixel = T>,
+{
+    let max = T::DEFAULT_MAX_VALUE.to_f32().unwrap();
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        Rgba(
+            image
+                .get_pixel(x, y)
+                .0
+                .map(|channel| T::from((channel.clamp(0.0, 1.0) * max).round()).unwrap()),
+        )
+    })
+}
+
+fn save_exr(
+    path: &Path,
+    beauty: &Rgba32FImage,
+    aovs: &[(Aov, Rgba32FImage)],
+    options: &OutputOptions,
+    metadata: &[(&str, String)],
+) -> exr::error::Result<()> {
+    let channel = |name: &str, image: &Rgba32FImage, index: usize, half: bool| {
+        let values = image.pixels().map(|pixel| pixel.0[index]);
+        let samples = if half {
+            FlatSamples::F16(values.map(f16::from_f32).collect())
+        } else {
+            FlatSamples::F32(values.collect())
+        };
+        AnyChannel::new(name, samples)
+    };
+
+    let mut channels = SmallVec::new();
+    for (index, name) in ["R", "G", "B", "A"].into_iter().enumerate() {
+        channels.push(channel(name, beauty, index, options.half));
+    }
+    for (aov, image) in aovs {
+        let half = options.half && !aov.needs_full_precision();
+        for (index, name) in aov.channels().iter().enumerate() {
+            channels.push(channel(
+                &format!("{}.{name}", aov.name()),
+                image,
+                index,
+                half,
+            ));
+        }
+    }
+
+    let mut attributes = LayerAttributes::default();
+    for (key, value) in metadata {
+        // EXR text is Latin-1, which leaves out few characters of the metadata, if any.
+        if let Some(value) = Text::new_or_none(value) {
+            attributes
+                .other
+                .insert(Text::from(*key), AttributeValue::Text(value));
+        }
+    }
+
+    let layer = Layer::new(
+        (beauty.width() as usize, beauty.height() as usize),
+        attributes,
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer).write().to_file(path)
+}
+
+fn save_png(
+    path: &Path,
+    image: &Rgba32FImage,
+    sixteen_bit: bool,
+    metadata: &[(&str, String)],
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width(),
+        image.height(),
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    for (key, value) in metadata {
+        encoder.add_text_chunk(key.to_string(), value.clone())?;
+    }
+
+    let data = if sixteen_bit {
+        encoder.set_depth(png::BitDepth::Sixteen);
+        to_display::<u16>(image)
+            .into_raw()
+            .into_iter()
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    } else {
+        encoder.set_depth(png::BitDepth::Eight);
+        to_display::<u8>(image).into_raw()
+    };
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()
+}
+
+/// Portable float map: little-endian RGB floats, from the bottom row to the top row.
+fn save_pfm(path: &Path, image: &Rgba32FImage) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+    for row in image.rows().rev() {
+        for pixel in row {
+            for channel in &pixel.0[..3] {
+                writer.write_all(&channel.to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+/// Replaces the run of `#` in `pattern` with the zero padded frame number.
+pub fn frame_path(pattern: &str, frame: usize) -> String {
+    let width = pattern.matches('#').count();
+    if width == 0 {
+        return pattern.to_string();
+    }
+
+    let start = pattern.find('#').unwrap();
+    format!(
+        "{}{frame:0width$}{}",
+        &pattern[..start],
+        &pattern[start + width..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exr_stores_aovs_as_named_channels() {
+        let beauty = Rgba32FImage::from_pixel(4, 2, Rgba([2.0, 0.5, 0.25, 1.0]));
+        let depth = Rgba32FImage::from_pixel(4, 2, Rgba([12.5, 12.5, 12.5, 1.0]));
+        let path = std::env::temp_dir().join("raytracer-output-test.exr");
+        let options = OutputOptions {
+            half: true,
+            ..OutputOptions::default()
+        };
+        save_layers(&path, &beauty, &[(Aov::Depth, depth)], &options, &[]).unwrap();
+
+        let image = exr::prelude::read_first_flat_layer_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let channels = &image.layer_data.channel_data.list;
+
+        let names = channels
+            .iter()
+            .map(|channel| channel.name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["A", "B", "G", "R", "depth.Z"]);
+
+        // Unclamped radiance in half floats, depth in full precision.
+        assert_eq!(channels[3].sample_data.value_by_flat_index(0).to_f32(), 2.0);
+        assert!(matches!(channels[4].sample_data, FlatSamples::F32(_)));
+        assert_eq!(
+            channels[4].sample_data.value_by_flat_index(7).to_f32(),
+            12.5
+        );
+    }
+
+    #[test]
+    fn png_stores_metadata_in_text_chunks() {
+        let image = Rgba32FImage::from_pixel(2, 2, Rgba([2.0, 0.5, 0.0, 1.0]));
+        let path = std::env::temp_dir().join("raytracer-output-test.png");
+        let metadata = [("Samples", "64".to_string())];
+        save_layers(&path, &image, &[], &OutputOptions::default(), &metadata).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let text = &reader.info().uncompressed_latin1_text;
+        assert_eq!(text.len(), 1);
+        assert_eq!(
+            (text[0].keyword.as_str(), text[0].text.as_str()),
+            ("Samples", "64")
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Preprocessor of the WGSL shaders in `src/shaders`, which composes them from modules and
+//! specializes them with feature defines set by the renderer.
+//!
+//! Directives start a line of their own:
+//!
+//! - `#include "file.wgsl"` inserts another file, once per composed shader, so every module can
+//!   include the modules it depends on and still be validated on its own.
+//! - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` keep or drop the lines between them
+//!   depending on whether `NAME` is defined.
+
+use std::{collections::BTreeMap, path::Path};
+
+use wgpu::naga::{
+    front::wgsl,
+    valid::{Capabilities, ValidationFlags, Validator},
+};
+
+/// The shader files built into the binary.
+const BUILT_IN: &[(&str, &str)] = &[
+    ("bsdf.wgsl", include_str!("shaders/bsdf.wgsl")),
+    ("bvh.wgsl", include_str!("shaders/bvh.wgsl")),
+    ("camera.wgsl", include_str!("shaders/camera.wgsl")),
+    ("common.wgsl", include_str!("shaders/common.wgsl")),
+    ("debug_views.wgsl", include_str!("shaders/debug_views.wgsl")),
+    ("denoise.wgsl", include_str!("shaders/denoise.wgsl")),
+    ("environment.wgsl", include_str!("shaders/environment.wgsl")),
+    ("ray_query.wgsl", include_str!("shaders/ray_query.wgsl")),
+    ("sampling.wgsl", include_str!("shaders/sampling.wgsl")),
+    ("shader.wgsl", include_str!("shaders/shader.wgsl")),
+];
+
+/// Shader sources by file name.
+#[derive(Clone)]
+pub struct ShaderFiles {
+    files: BTreeMap<String, String>,
+}
+
+impl ShaderFiles {
+    pub fn built_in() -> Self {
+        Self::from_sources(BUILT_IN)
+    }
+
+    /// Reads every `.wgsl` file in `directory`.
+    pub fn load(directory: &Path) -> Result<Self, String> {
+        let error = |err: std::io::Error| format!("{}: {err}", directory.display());
+        let mut files = BTreeMap::new();
+        for entry in std::fs::read_dir(directory).map_err(error)? {
+            let path = entry.map_err(error)?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "wgsl")
+            {
+                let source = std::fs::read_to_string(&path)
+                    .map_err(|err| format!("{}: {err}", path.display()))?;
+                files.insert(path.file_name().unwrap().to_string_lossy().into(), source);
+            }
+        }
+        Ok(Self { files })
+    }
+
+    fn from_sources(sources: &[(&str, &str)]) -> Self {
+        Self {
+            files: sources
+                .iter()
+                .map(|(name, source)| (name.to_string(), source.to_string()))
+                .collect(),
+        }
+    }
+
+    /// Composes `file` and the files it includes with the given names defined.
+    pub fn preprocess(&self, file: &str, defines: &[&str]) -> Result<String, String> {
+        let mut output = String::new();
+        let mut included = Vec::new();
+        self.expand(file, defines, &mut included, &mut output)?;
+        Ok(output)
+    }
+
+    fn expand<'a>(
+        &'a self,
+        file: &'a str,
+        defines: &[&str],
+        included: &mut Vec<&'a str>,
+        output: &mut String,
+    ) -> Result<(), String> {
+        let source = self
+            .files
+            .get(file)
+            .ok_or_else(|| format!("missing shader file {file}"))?;
+        included.push(file);
+
+        // Whether the enclosing conditionals keep their lines, and whether an `#else` was seen.
+        let mut conditionals: Vec<(bool, bool)> = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: &str| format!("{file}:{}: {message}", index + 1);
+            let active = conditionals.iter().all(|(active, _)| *active);
+
+            let Some(directive) = line.trim().strip_prefix('#') else {
+                if active {
+                    output.push_str(line);
+                    output.push('\n');
+                }
+                continue;
+            };
+
+            let (name, argument) = directive
+                .split_once(char::is_whitespace)
+                .map_or((directive, ""), |(name, argument)| (name, argument.trim()));
+            match name {
+                "include" => {
+                    let include = argument
+                        .strip_prefix('"')
+                        .and_then(|argument| argument.strip_suffix('"'))
+                        .ok_or_else(|| error("expected #include \"file.wgsl\""))?;
+                    let include = self
+                        .files
+                        .get_key_value(include)
+                        .ok_or_else(|| error(&format!("missing shader file {include}")))?
+                        .0;
+                    if active && !included.contains(&include.as_str()) {
+                        self.expand(include, defines, included, output)?;
+                    }
+                }
+                "ifdef" | "ifndef" if !argument.is_empty() => {
+                    let defined = defines.contains(&argument);
+                    conditionals.push((defined == (name == "ifdef"), false));
+                }
+                "else" => match conditionals.last_mut() {
+                    Some((active, seen_else @ false)) => {
+                        *active = !*active;
+                        *seen_else = true;
+                    }
+                    _ => return Err(error("#else without #ifdef")),
+                },
+                "endif" => {
+                    conditionals
+                        .pop()
+                        .ok_or_else(|| error("#endif without #ifdef"))?;
+                }
+                _ => return Err(error(&format!("invalid directive #{directive}"))),
+            }
+        }
+
+        if !conditionals.is_empty() {
+            return Err(format!("{file}: missing #endif"));
+        }
+        Ok(())
+    }
+}
+
+/// Parses and validates a composed shader with naga, the way the device does when it creates
+/// the shader module.
+pub fn validate(source: &str) -> Result<(), String> {
+    let module = wgsl::parse_str(source).map_err(|err| err.emit_to_string(source))?;
+    Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|err| err.emit_to_string(source))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every define the shaders check.
+    const DEFINES: &[&str] = &["RAY_QUERY", "RGBA8_RENDER_TEXTURE", "AOVS", "DEBUG_VIEWS"];
+
+    #[test]
+    fn includes_once_and_keeps_defined_branches() {
+        let files = ShaderFiles::from_sources(&[
+            (
+                "main.wgsl",
+                "#include \"a.wgsl\"\n#include \"b.wgsl\"\n#ifdef X\nx\n#else\nnot x\n#endif\nmain",
+            ),
+            ("a.wgsl", "#ifndef Y\n#include \"b.wgsl\"\n#endif\na"),
+            ("b.wgsl", "b"),
+        ]);
+
+        assert_eq!(
+            files.preprocess("main.wgsl", &[]).unwrap(),
+            "b\na\nnot x\nmain\n"
+        );
+        assert_eq!(
+            files.preprocess("main.wgsl", &["X", "Y"]).unwrap(),
+            "a\nb\nx\nmain\n"
+        );
+
+        let invalid = |source| {
+            ShaderFiles::from_sources(&[("main.wgsl", source)])
+                .preprocess("main.wgsl", &[])
+                .unwrap_err()
+        };
+        assert_eq!(invalid("#ifdef X\n"), "main.wgsl: missing #endif");
+        assert_eq!(invalid("x\n#endif"), "main.wgsl:2: #endif without #ifdef");
+        assert_eq!(
+            invalid("#include \"missing.wgsl\""),
+            "main.wgsl:1: missing shader file missing.wgsl"
+        );
+    }
+
+    /// Validates every module on its own and the path tracer in every pipeline variant.
+    #[test]
+    fn shader_modules_validate() {
+        let files = ShaderFiles::built_in();
+        for (file, _) in BUILT_IN {
+            for variant in 0..1 << DEFINES.len() {
+                let defines = (0..DEFINES.len())
+                    .filter(|bit| variant & (1 << bit) != 0)
+                    .map(|bit| DEFINES[bit])
+                    .collect::<Vec<_>>();
+                let source = files.preprocess(file, &defines).unwrap();
+                if let Err(err) = validate(&source) {
+                    panic!("{file} with {defines:?}:\n{err}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use std::f32::consts::PI;
+
+use glam::{Mat4, UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
+use image::Rgba32FImage;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    bvh::{Bvh, Hit, Ray},
+    camera::{Camera, Projection},
+    model::{Material, Model, Vertex, MATERIAL_MIRROR},
+    noise::load_noise_images,
+    renderer::RenderSettings,
+    sampler::{dimension_seed, hash, Sampler, SamplerTables},
+};
+
+const DIMENSION_PIXEL: u32 = 0;
+const DIMENSION_FIRST_BOUNCE: u32 = 1;
+const DIMENSIONS_PER_BOUNCE: u32 = 2;
+const DIMENSION_DIRECTION: u32 = 0;
+const DIMENSION_ROULETTE: u32 = 1;
+
+/// Cpu implementation of the path tracer in `shader.wgsl`, used as ground truth for the gpu and
+/// to render on machines without ray query support.
+///
+/// Every step follows the shader, including its random number sequence, so both produce
+/// comparable images for the same camera and sample count.
+pub struct ReferenceRenderer {
+    vertices: Vec<Vertex>,
+    materials: Vec<Material>,
+    bvh: Bvh,
+    skybox: Rgba32FImage,
+    noise: Vec<Rgba32FImage>,
+    sampler_tables: SamplerTables,
+    sampler: Sampler,
+    settings: RenderSettings,
+    size: PhysicalSize<u32>,
+}
+
+/// Sample being traced, the inputs of `sample_2d` in the shader.
+struct SampleContext {
+    gid: UVec2,
+    index: u32,
+    seed: u32,
+}
+
+struct CameraMatrices {
+    inverse_proj: Mat4,
+    inverse_view: Mat4,
+    camera: Camera,
+}
+
+impl ReferenceRenderer {
+    pub fn new(size: PhysicalSize<u32>, model: &Model, skybox: Rgba32FImage) -> Self {
+        Self {
+            vertices: model.vertices.clone(),
+            materials: model.materials.clone(),
+            bvh: Bvh::build(&model.vertices),
+            skybox,
+            noise: load_noise_images(),
+            sampler_tables: SamplerTables::generate(),
+            sampler: Sampler::Sobol,
+            settings: RenderSettings::default(),
+            size,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: RenderSettings) {
+        self.settings = settings;
+    }
+
+    pub fn set_sampler(&mut self, sampler: Sampler) {
+        self.sampler = sampler;
+    }
+
+    /// Renders `num_samples` samples per pixel and returns the linear mean radiance, the same
+    /// `seed` always produces the same image.
+    pub fn render(&self, camera: &Camera, num_samples: u32, seed: u32) -> Rgba32FImage {
+        self.render_with_sample_counts(camera, num_samples, seed).0
+    }
+
+    /// Like [`Self::render`], but also returns the number of samples each pixel received in
+    /// row-major order. With adaptive sampling, converged pixels stop early while the others
+    /// take the full `num_samples`; the samples they skip are not spent elsewhere.
+    pub fn render_with_sample_counts(
+        &self,
+        camera: &Camera,
+        num_samples: u32,
+        seed: u32,
+    ) -> (Rgba32FImage, Vec<u32>) {
+        let matrices = CameraMatrices {
+            inverse_proj: camera.calculate_projection(&self.size).inverse(),
+            inverse_view: camera.calculate_view().inverse(),
+            camera: *camera,
+        };
+
+        let width = self.size.width as usize;
+        let mut pixels = vec![0.0; width * self.size.height as usize * 4];
+        let mut sample_counts = vec![0; width * self.size.height as usize];
+
+        let num_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let rows_per_thread = (self.size.height as usize).div_ceil(num_threads).max(1);
+
+        std::thread::scope(|scope| {
+            let chunks = pixels
+                .chunks_mut(rows_per_thread * width * 4)
+                .zip(sample_counts.chunks_mut(rows_per_thread * width));
+            for (chunk_index, (chunk, counts)) in chunks.enumerate() {
+                let matrices = &matrices;
+                scope.spawn(move || {
+                    let pixels = chunk.chunks_exact_mut(4).zip(counts);
+                    for (index, (pixel, count)) in pixels.enumerate() {
+                        let gid = UVec2::new(
+                            (index % width) as u32,
+                            (chunk_index * rows_per_thread + index / width) as u32,
+                        );
+                        let (color, samples) = self.render_pixel(matrices, gid, num_samples, seed);
+                        pixel.copy_from_slice(&[color.x, color.y, color.z, 1.0]);
+                        *count = samples;
+                    }
+                });
+            }
+        });
+
+        let image = Rgba32FImage::from_raw(self.size.width, self.size.height, pixels).unwrap();
+        (image, sample_counts)
+    }
+
+    fn render_pixel(
+        &self,
+        matrices: &CameraMatrices,
+        gid: UVec2,
+        num_samples: u32,
+        seed: u32,
+    ) -> (Vec3, u32) {
+        let size = Vec2::new(self.size.width as f32, self.size.height as f32);
+        let mut accumulated_color = Vec3::ZERO;
+        let mut m2 = 0.0;
+
+        let mut samples = 0;
+        for sample in 0..num_samples {
+            if self
+                .settings
+                .is_converged(luminance(accumulated_color), m2, sample)
+            {
+                break;
+            }
+            samples += 1;
+
+            let mut rng = Rng((gid
+                .x
+                .wrapping_mul(1973)
+                .wrapping_add(gid.y.wrapping_mul(9277))
+                .wrapping_add(sample.wrapping_mul(26699))
+                ^ seed.wrapping_mul(0x9e3779b9))
+                | 1);
+
+            let context = SampleContext {
+                gid,
+                index: sample,
+                seed,
+            };
+
+            let pixel = gid.as_vec2() + self.sample_2d(&context, DIMENSION_PIXEL, &mut rng) - 0.5;
+            let ndc = Vec2::new(pixel.x / size.x * 2.0 - 1.0, 1.0 - pixel.y / size.y * 2.0);
+
+            let ray_color = self.trace_ray(primary_ray(matrices, ndc), &context, &mut rng);
+
+            let previous_mean = luminance(accumulated_color);
+            accumulated_color = accumulated_color.lerp(ray_color, 1.0 / (sample as f32 + 1.0));
+            let sample_luminance = luminance(ray_color);
+            m2 += (sample_luminance - previous_mean)
+                * (sample_luminance - luminance(accumulated_color));
+        }
+
+        (accumulated_color, samples)
+    }
+
+    fn trace_ray(&self, ray_desc: Ray, context: &SampleContext, rng: &mut Rng) -> Vec3 {
+        let mut ray = ray_desc;
+        let mut color = Vec3::ONE;
+
+        let mut intersection = self.bvh.intersect(&self.vertices, &ray);
+
+        for i in 0..self.settings.max_depth {
+            rng.0 = rng.0.wrapping_add(i.wrapping_mul(2351341));
+            let dimension = DIMENSION_FIRST_BOUNCE + i * DIMENSIONS_PER_BOUNCE;
+
+            if i >= self.settings.rr_min_depth {
+                let probability = color
+                    .max_element()
+                    .clamp(self.settings.rr_min_probability, 1.0);
+                if self
+                    .sample_2d(context, dimension + DIMENSION_ROULETTE, rng)
+                    .x
+                    >= probability
+                {
+                    break;
+                }
+                color /= probability;
+            }
+
+            if let Some(Hit {
+                t,
+                primitive_index,
+                barycentrics,
+            }) = intersection
+            {
+                if t < 0.001 {
+                    break;
+                }
+
+                let first = primitive_index as usize * 3;
+                let n0 = self.vertices[first].normal;
+                let n1 = self.vertices[first + 1].normal;
+                let n2 = self.vertices[first + 2].normal;
+                let material = self.materials[self.vertices[first].material as usize];
+
+                let u = barycentrics.x;
+                let v = barycentrics.y;
+                let w = 1.0 - u - v;
+
+                let mut normal = (w * n0 + u * n1 + v * n2).normalize();
+                if normal.dot(ray.dir) > 0.0 {
+                    normal = -normal;
+                }
+
+                ray.origin += ray.dir * t;
+                if material.kind == MATERIAL_MIRROR {
+                    ray.dir = reflect(ray.dir, normal);
+                } else {
+                    let sample = self.sample_2d(context, dimension + DIMENSION_DIRECTION, rng);
+                    ray.dir = cosine_sample_hemisphere(normal, sample);
+                    color *= material.albedo;
+                }
+
+                intersection = self.bvh.intersect(&self.vertices, &ray);
+            } else if ray.dir.y < 0.0 {
+                let t = -ray.origin.y / ray.dir.y;
+                let normal = Vec3::Y;
+
+                color *= self.sky_color(&ray);
+
+                ray.origin += ray.dir * t;
+                let sample = self.sample_2d(context, dimension + DIMENSION_DIRECTION, rng);
+                ray.dir = cosine_sample_hemisphere(normal, sample);
+
+                intersection = self.bvh.intersect(&self.vertices, &ray);
+            } else {
+                return color * self.sky_color(&ray);
+            }
+        }
+
+        Vec3::ZERO
+    }
+
+    fn sky_color(&self, ray: &Ray) -> Vec3 {
+        let theta = ray.dir.z.atan2(ray.dir.x);
+        let phi = ray.dir.y.acos();
+
+        let u = (theta + PI) / (2.0 * PI);
+        let v = phi / PI;
+
+        let (width, height) = self.skybox.dimensions();
+        let x = ((width as f32 * u) as u32).min(width - 1);
+        let y = ((height as f32 * v) as u32).min(height - 1);
+
+        Vec4::from(self.skybox.get_pixel(x, y).0).xyz()
+    }
+
+    /// Point in [0, 1)^2 of the current sample for one 2D sample dimension.
+    fn sample_2d(&self, context: &SampleContext, dimension: u32, rng: &mut Rng) -> Vec2 {
+        let seed = || dimension_seed(context.seed, context.gid, dimension);
+        match self.sampler {
+            Sampler::Sobol => self.sampler_tables.sobol_2d(context.index, seed()),
+            Sampler::Halton => self
+                .sampler_tables
+                .halton_2d(context.index, dimension, seed()),
+            Sampler::BlueNoise => self.blue_noise_2d(context.gid, dimension, rng),
+        }
+    }
+
+    fn blue_noise_2d(&self, gid: UVec2, dimension: u32, rng: &mut Rng) -> Vec2 {
+        let layer_size = self.noise[0].dimensions();
+        let random_offset = [rng.wang(), rng.wang(), rng.wang()];
+
+        let x = gid.x.wrapping_add(random_offset[0]) % layer_size.0;
+        let y = gid.y.wrapping_add(random_offset[1]) % layer_size.1;
+        let layer = dimension.wrapping_add(random_offset[2]) % self.noise.len() as u32;
+
+        Vec4::from(self.noise[layer as usize].get_pixel(x, y).0).xy()
+    }
+}
+
+/// Same ray generation as `primary_ray` in the shader.
+fn primary_ray(matrices: &CameraMatrices, ndc: Vec2) -> Ray {
+    let camera = &matrices.camera;
+    let mut origin_view_space = Vec3::ZERO;
+
+    let direction_view_space = match camera.projection {
+        Projection::Perspective => (matrices.inverse_proj * ndc.extend(0.0).extend(1.0))
+            .xyz()
+            .normalize(),
+        Projection::Orthographic => {
+            origin_view_space = (matrices.inverse_proj * ndc.extend(0.0).extend(1.0))
+                .xy()
+                .extend(0.0);
+            Vec3::Z
+        }
+        Projection::Equirectangular | Projection::StereoEquirectangular => {
+            let mut panorama = ndc;
+            let mut eye = 0.0;
+            if camera.projection == Projection::StereoEquirectangular {
+                eye = if ndc.y > 0.0 { -0.5 } else { 0.5 };
+                panorama.y = if ndc.y > 0.0 {
+                    ndc.y * 2.0 - 1.0
+                } else {
+                    ndc.y * 2.0 + 1.0
+                };
+            }
+
+            let longitude = panorama.x * PI;
+            let latitude = panorama.y * PI / 2.0;
+            origin_view_space =
+                eye * camera.eye_separation * Vec3::new(longitude.cos(), 0.0, -longitude.sin());
+            Vec3::new(
+                latitude.cos() * longitude.sin(),
+                latitude.sin(),
+                latitude.cos() * longitude.cos(),
+            )
+        }
+    };
+
+    Ray {
+        origin: matrices.inverse_view.transform_point3(origin_view_space),
+        dir: matrices
+            .inverse_view
+            .transform_vector3(direction_view_space)
+            .normalize(),
+        t_min: camera.near,
+        t_max: camera.far,
+    }
+}
+
+/// Same cosine weighted sampling as `cosine_sample_hemisphere` in the shader.
+fn cosine_sample_hemisphere(normal: Vec3, sample: Vec2) -> Vec3 {
+    let r = sample.x.sqrt();
+    let phi = 2.0 * PI * sample.y;
+    let local = Vec3::new(
+        r * phi.cos(),
+        r * phi.sin(),
+        (1.0 - sample.x).max(0.0).sqrt(),
+    );
+
+    let sign = if normal.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    let tangent = Vec3::new(
+        1.0 + sign * normal.x * normal.x * a,
+        sign * b,
+        -sign * normal.x,
+    );
+    let bitangent = Vec3::new(b, sign + normal.y * normal.y * a, -normal.y);
+
+    (local.x * tangent + local.y * bitangent + local.z * normal).normalize()
+}
+
+fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn reflect(dir: Vec3, normal: Vec3) -> Vec3 {
+    dir - 2.0 * dir.dot(normal) * normal
+}
+
+/// Wang hash random number generator, matching `rand_wang` in the shader.
+struct Rng(u32);
+
+impl Rng {
+    fn wang(&mut self) -> u32 {
+        self.0 = hash(self.0);
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::SamplerTables;
+
+    #[test]
+    fn cosine_sampling_matches_lambertian_lobe() {
+        let tables = SamplerTables::generate();
+        let num_samples = 4096;
+
+        for normal in [Vec3::Y, -Vec3::Z, Vec3::new(1.0, -2.0, 0.5).normalize()] {
+            let mean_cosine = (0..num_samples)
+                .map(|index| {
+                    let dir = cosine_sample_hemisphere(normal, tables.sobol_2d(index, 1));
+                    assert!((dir.length() - 1.0).abs() < 1e-4);
+                    dir.dot(normal)
+                })
+                .sum::<f32>()
+                / num_samples as f32;
+
+            // The mean of cos(theta) under a pdf of cos(theta) / pi is 2/3.
+            assert!(
+                (mean_cosine - 2.0 / 3.0).abs() < 1e-3,
+                "{normal}: {mean_cosine}"
+            );
+        }
+    }
+}
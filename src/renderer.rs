@@ -1,77 +1,225 @@
-use std::{num::NonZero, sync::Arc};
+use std::{collections::HashMap, num::NonZero, path::Path, sync::Arc};
 
 use bytemuck::{Pod, Zeroable};
-use glam::Mat4;
+use glam::{Mat4, Vec3};
+use image::Rgba32FImage;
 use wgpu::{
     hal::AccelerationStructureGeometryFlags,
-    include_wgsl,
     util::{BufferInitDescriptor, DeviceExt},
-    AccelerationStructureFlags, AccelerationStructureUpdateMode, Backends, BindGroup,
-    BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
-    BindingResource, BindingType, BlasBuildEntry, BlasGeometries, BlasGeometrySizeDescriptors,
-    BlasTriangleGeometry, BlasTriangleGeometrySizeDescriptor, Buffer, BufferBindingType,
-    BufferDescriptor, BufferUsages, CommandEncoderDescriptor, CompositeAlphaMode,
-    ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, CreateBlasDescriptor,
-    CreateTlasDescriptor, Device, DeviceDescriptor, Extent3d, Features, Instance,
-    InstanceDescriptor, Limits, MemoryHints, PipelineLayoutDescriptor, PowerPreference,
-    PresentMode, PushConstantRange, Queue, RequestAdapterOptions, ShaderStages,
-    StorageTextureAccess, Surface, SurfaceConfiguration, SurfaceError, Texture, TextureDescriptor,
-    TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor, TextureViewDimension,
-    TlasInstance, TlasPackage, VertexFormat,
+    AccelerationStructureFlags, AccelerationStructureUpdateMode, BindGroup, BindGroupDescriptor,
+    BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+    BlasBuildEntry, BlasGeometries, BlasGeometrySizeDescriptors, BlasTriangleGeometry,
+    BlasTriangleGeometrySizeDescriptor, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    CommandEncoderDescriptor, CompositeAlphaMode, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, CreateBlasDescriptor, CreateTlasDescriptor, Device,
+    DeviceDescriptor, ErrorFilter, Extent3d, Instance, Limits, Maintain, MapMode, MemoryHints,
+    PipelineLayout, PipelineLayoutDescriptor, PresentMode, PushConstantRange, Queue,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StorageTextureAccess, Surface,
+    SurfaceConfiguration, SurfaceError, TexelCopyBufferInfo, TexelCopyBufferLayout, Texture,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor,
+    TextureViewDimension, TlasInstance, TlasPackage, VertexFormat, COPY_BYTES_PER_ROW_ALIGNMENT,
 };
 use winit::{dpi::PhysicalSize, window::Window};
 
 use crate::{
-    model::{load_model, Vertex},
+    aov::Aov,
+    bvh::Bvh,
+    camera::{Camera, Tile},
+    denoiser::{Denoiser, DenoiserInputs},
+    gpu::{
+        create_instance, render_format, render_format_defines, required_limits, select_adapter,
+        GpuError, GpuOptions, OPTIONAL_FEATURES, RAY_QUERY_FEATURES, REQUIRED_FEATURES,
+    },
+    model::{Model, Vertex},
     noise::load_noise,
+    preprocessor::{validate, ShaderFiles},
+    sampler::{create_sampler_tables, Sampler},
     skybox::load_skybox,
 };
 
-const CAMERA_BUFFER_SIZE: usize = 128;
+const CAMERA_BUFFER_SIZE: usize = size_of::<CameraMatrices>();
+pub const WORKGROUP_SIZE: u32 = 10;
 
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
 struct CameraMatrices {
     inverse_proj: Mat4,
     inverse_view: Mat4,
+    near: f32,
+    far: f32,
+    projection: u32,
+    eye_separation: f32,
+    previous_view_proj: Mat4,
+    previous_eye: Vec3,
+    previous_projection: u32,
+    previous_forward: Vec3,
+    _pad0: f32,
+    tile_offset: [u32; 2],
+    _pad1: [u32; 2],
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
 struct PushConstants {
-    time: f32,
+    seed: u32,
     num_samples: u32,
+    dispatch_samples: u32,
+    sampler_type: u32,
+    max_depth: u32,
+    rr_min_depth: u32,
+    rr_min_probability: f32,
+    adaptive_threshold: f32,
+    adaptive_min_samples: u32,
+    sample_heatmap: u32,
+    history: u32,
+    aov_view: u32,
+    sample_offset: u32,
+}
+
+/// Size of the push constants, a required limit of the device.
+pub const PUSH_CONSTANT_SIZE: u32 = size_of::<PushConstants>() as u32;
+
+/// What the next dispatch starts from, matching the `HISTORY_` constants in the shader.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum History {
+    /// Adds to the accumulation.
+    Continue,
+    /// Discards the accumulation.
+    Restart,
+    /// Reprojects the samples of the previous camera, see [`Renderer::set_temporal_reprojection`].
+    Reproject,
+}
+
+/// Path tracing parameters, shared by the gpu and the cpu reference renderer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderSettings {
+    /// Maximum number of bounces of a path.
+    pub max_depth: u32,
+    /// Number of bounces before Russian roulette may terminate a path.
+    pub rr_min_depth: u32,
+    /// Lower bound of the survival probability, which otherwise follows the path throughput.
+    pub rr_min_probability: f32,
+    /// Pixels stop sampling once the standard error of their mean luminance drops below this
+    /// fraction of the luminance, zero samples every pixel equally. This only ends converged
+    /// pixels early, the other pixels still receive the requested samples and no more.
+    pub adaptive_threshold: f32,
+    /// Samples every pixel receives before it may count as converged.
+    pub adaptive_min_samples: u32,
+}
+
+/// Keeps the relative error of almost black pixels from demanding endless samples.
+const ADAPTIVE_MIN_LUMINANCE: f32 = 0.01;
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            max_depth: 10,
+            rr_min_depth: 3,
+            rr_min_probability: 0.05,
+            adaptive_threshold: 0.01,
+            adaptive_min_samples: 32,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Same convergence test as `is_converged` in the shader, for a pixel with `count` samples,
+    /// mean luminance `mean` and sum of squared deviations `m2`.
+    pub fn is_converged(&self, mean: f32, m2: f32, count: u32) -> bool {
+        if self.adaptive_threshold <= 0.0 || count < self.adaptive_min_samples.max(2) {
+            return false;
+        }
+        let standard_error = (m2 / (count - 1) as f32 / count as f32).sqrt();
+        standard_error <= self.adaptive_threshold * mean.max(ADAPTIVE_MIN_LUMINANCE)
+    }
+}
+
+/// Everything the accumulation of a [`Renderer`] consists of, for checkpoints.
+pub struct AccumulationState {
+    pub size: PhysicalSize<u32>,
+    pub num_samples: u32,
+    /// Texels of the accumulation, variance, normal depth and albedo textures.
+    pub textures: Vec<Vec<f32>>,
 }
 
 pub struct Renderer {
-    surface: Surface<'static>,
+    surface: Option<Surface<'static>>,
     device: Device,
     queue: Queue,
-    pipeline: ComputePipeline,
+    /// Variants of the path tracer compiled so far.
+    pipelines: HashMap<PipelineVariant, ComputePipeline>,
+    pipeline_layout: PipelineLayout,
+    shader_files: ShaderFiles,
+    /// Defines of every variant, set by the features of the adapter.
+    defines: Vec<&'static str>,
     render_texture: Texture,
+    accumulation_texture: Texture,
+    normal_depth_texture: Texture,
+    albedo_texture: Texture,
+    position_texture: Texture,
+    id_texture: Texture,
     bind_group: BindGroup,
+    denoiser: Denoiser,
+    /// Textures the shader reprojects, paired with the copy that keeps their state for the
+    /// previous camera.
+    history_textures: [(Texture, Texture); 4],
     camera_buffer: Buffer,
-    window_size: PhysicalSize<u32>,
+    size: PhysicalSize<u32>,
     num_samples: u32,
+    history: History,
+    temporal_reprojection: bool,
+    camera: Option<Camera>,
+    /// The camera that accumulated the samples in the history textures.
+    history_camera: Option<Camera>,
+    sampler: Sampler,
+    settings: RenderSettings,
+    sample_heatmap: bool,
+    denoise: bool,
+    aovs: bool,
+    aov_view: Option<Aov>,
+    tile: Option<Tile>,
+    sample_offset: u32,
 }
 
 impl Renderer {
-    pub async fn new(window: Arc<Window>) -> Self {
-        let instance = Instance::new(&InstanceDescriptor {
-            backends: Backends::VULKAN,
-            ..Default::default()
-        });
+    pub async fn new(
+        window: Arc<Window>,
+        model: &Model,
+        options: &GpuOptions,
+    ) -> Result<Self, GpuError> {
+        let instance = create_instance(options);
+        let surface = instance
+            .create_surface(window.clone())
+            .map_err(GpuError::CreateSurface)?;
+
+        Self::create(
+            &instance,
+            options,
+            Some(surface),
+            window.inner_size(),
+            model,
+        )
+        .await
+    }
 
-        let surface = instance.create_surface(window.clone()).unwrap();
+    /// Creates a renderer without a window, whose result can only be read back with
+    /// [`Renderer::read_output`].
+    pub async fn new_headless(
+        size: PhysicalSize<u32>,
+        model: &Model,
+        options: &GpuOptions,
+    ) -> Result<Self, GpuError> {
+        Self::create(&create_instance(options), options, None, size, model).await
+    }
 
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                power_preference: PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
+    async fn create(
+        instance: &Instance,
+        options: &GpuOptions,
+        surface: Option<Surface<'static>>,
+        size: PhysicalSize<u32>,
+        model: &Model,
+    ) -> Result<Self, GpuError> {
+        let adapter = select_adapter(instance, options, surface.as_ref())?;
 
         log::info!(
             "Gpu: {}, Backend: {}",
@@ -79,44 +227,59 @@ impl Renderer {
             adapter.get_info().backend
         );
 
+        let max_size = adapter.limits().max_texture_dimension_2d;
+        if size.width > max_size || size.height > max_size {
+            return Err(GpuError::ImageTooLarge {
+                adapter: adapter.get_info().name,
+                width: size.width,
+                height: size.height,
+                max_size,
+            });
+        }
+
         let (device, queue) = adapter
             .request_device(
                 &DeviceDescriptor {
                     label: None,
-                    required_features: Features::BGRA8UNORM_STORAGE
-                        | Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
-                        | Features::PUSH_CONSTANTS
-                        | Features::EXPERIMENTAL_RAY_TRACING_ACCELERATION_STRUCTURE
-                        | Features::EXPERIMENTAL_RAY_QUERY,
+                    required_features: REQUIRED_FEATURES | (adapter.features() & OPTIONAL_FEATURES),
                     required_limits: Limits {
-                        max_push_constant_size: size_of::<PushConstants>() as u32,
-                        ..Default::default()
+                        max_texture_dimension_2d: max_size,
+                        ..required_limits()
                     },
                     memory_hints: MemoryHints::default(),
                 },
                 None,
             )
             .await
-            .unwrap();
+            .map_err(GpuError::RequestDevice)?;
 
-        let window_size = window.inner_size();
+        let texture_format = render_format(device.features());
+        if let Some(surface) = &surface {
+            surface.configure(
+                &device,
+                &SurfaceConfiguration {
+                    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_DST,
+                    format: texture_format,
+                    width: size.width,
+                    height: size.height,
+                    present_mode: PresentMode::Immediate,
+                    alpha_mode: CompositeAlphaMode::Auto,
+                    view_formats: vec![],
+                    desired_maximum_frame_latency: 2,
+                },
+            );
+        }
 
-        let texture_format = TextureFormat::Bgra8Unorm;
-        surface.configure(
-            &device,
-            &SurfaceConfiguration {
-                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_DST,
-                format: texture_format,
-                width: window_size.width,
-                height: window_size.height,
-                present_mode: PresentMode::Immediate,
-                alpha_mode: CompositeAlphaMode::Auto,
-                view_formats: vec![],
-                desired_maximum_frame_latency: 2,
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("vertex buffer"),
+            contents: bytemuck::cast_slice(&model.vertices),
+            usage: if device.features().contains(RAY_QUERY_FEATURES) {
+                BufferUsages::BLAS_INPUT | BufferUsages::STORAGE
+            } else {
+                BufferUsages::STORAGE
             },
-        );
-
-        let shader_module = device.create_shader_module(include_wgsl!("shader.wgsl"));
+        });
+        let geometry = SceneGeometry::new(&device, &queue, model, &vertex_buffer);
 
         let camera_buffer = device.create_buffer(&BufferDescriptor {
             label: None,
@@ -127,66 +290,182 @@ impl Renderer {
 
         let skybox_texture_view = load_skybox(&device, &queue);
 
-        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: None,
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadWrite,
-                        format: texture_format,
-                        view_dimension: TextureViewDimension::D2,
-                    },
-                    count: None,
+        let mut layout_entries = vec![
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: texture_format,
+                    view_dimension: TextureViewDimension::D2,
                 },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: Some(NonZero::new(CAMERA_BUFFER_SIZE as u64).unwrap()),
-                    },
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(NonZero::new(CAMERA_BUFFER_SIZE as u64).unwrap()),
                 },
-                BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::AccelerationStructure,
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
                 },
-                BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadOnly,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2Array,
                 },
-                BindGroupLayoutEntry {
-                    binding: 4,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadOnly,
-                        format: TextureFormat::Rgba32Float,
-                        view_dimension: TextureViewDimension::D2Array,
-                    },
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadOnly,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2,
                 },
-                BindGroupLayoutEntry {
-                    binding: 5,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadOnly,
-                        format: TextureFormat::Rgba32Float,
-                        view_dimension: TextureViewDimension::D2,
-                    },
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 6,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2,
                 },
-            ],
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 7,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 8,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 9,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: TextureFormat::R32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 10,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 11,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 12,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadOnly,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 13,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadOnly,
+                    format: TextureFormat::R32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 14,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadOnly,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 15,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadOnly,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 16,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 17,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+        ];
+        layout_entries.extend(geometry.layout_entries());
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &layout_entries,
         });
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
@@ -194,26 +473,24 @@ impl Renderer {
             bind_group_layouts: &[&bind_group_layout],
             push_constant_ranges: &[PushConstantRange {
                 stages: ShaderStages::COMPUTE,
-                range: 0..(size_of::<PushConstants>() as u32),
+                range: 0..PUSH_CONSTANT_SIZE,
             }],
         });
 
-        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            module: &shader_module,
-            entry_point: Some("render"),
-            compilation_options: Default::default(),
-            cache: None,
-        });
+        let mut defines = render_format_defines(texture_format).to_vec();
+        if device.features().contains(RAY_QUERY_FEATURES) {
+            defines.push("RAY_QUERY");
+        }
+
+        let texture_size = Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        };
 
         let render_texture = device.create_texture(&TextureDescriptor {
             label: None,
-            size: Extent3d {
-                width: window_size.width,
-                height: window_size.height,
-                depth_or_array_layers: 1,
-            },
+            size: texture_size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
@@ -224,127 +501,443 @@ impl Renderer {
 
         let render_texture_view = render_texture.create_view(&TextureViewDescriptor::default());
 
-        let tlas = device.create_tlas(&CreateTlasDescriptor {
-            label: None,
-            max_instances: 1,
-            flags: AccelerationStructureFlags::PREFER_FAST_TRACE,
-            update_mode: AccelerationStructureUpdateMode::Build,
+        let accumulation_texture = device.create_texture(&TextureDescriptor {
+            label: Some("accumulation texture"),
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::STORAGE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::COPY_DST,
+            view_formats: &[],
         });
 
-        let model = load_model(include_str!("../assets/models/E30_Final01.obj"));
+        let accumulation_texture_view =
+            accumulation_texture.create_view(&TextureViewDescriptor::default());
 
-        let geometry_size = BlasTriangleGeometrySizeDescriptor {
-            vertex_format: VertexFormat::Float32x3,
-            vertex_count: (model.vertices.len() / 3) as u32,
-            index_format: None,
-            index_count: None,
-            flags: AccelerationStructureGeometryFlags::OPAQUE,
-        };
-        let blas = device.create_blas(
-            &CreateBlasDescriptor {
-                label: None,
-                flags: AccelerationStructureFlags::PREFER_FAST_TRACE,
-                update_mode: AccelerationStructureUpdateMode::Build,
-            },
-            BlasGeometrySizeDescriptors::Triangles {
-                descriptors: vec![geometry_size.clone()],
-            },
-        );
+        let variance_texture = device.create_texture(&TextureDescriptor {
+            label: Some("variance texture"),
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::STORAGE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
 
-        let tlas_package = TlasPackage::new_with_instances(
-            tlas,
-            vec![Some(TlasInstance::new(
-                &blas,
-                Mat4::IDENTITY.to_cols_array()[..12].try_into().unwrap(),
-                0,
-                0xff,
-            ))],
-        );
+        let variance_texture_view = variance_texture.create_view(&TextureViewDescriptor::default());
 
-        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("vertex buffer"),
-            contents: bytemuck::cast_slice(&model.vertices),
-            usage: BufferUsages::BLAS_INPUT | BufferUsages::STORAGE,
+        let normal_depth_texture = device.create_texture(&TextureDescriptor {
+            label: Some("normal depth texture"),
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::STORAGE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::COPY_DST,
+            view_formats: &[],
         });
 
-        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
-        encoder.build_acceleration_structures(
-            std::iter::once(&BlasBuildEntry {
-                blas: &blas,
-                geometry: BlasGeometries::TriangleGeometries(vec![BlasTriangleGeometry {
-                    size: &geometry_size,
-                    vertex_buffer: &vertex_buffer,
-                    first_vertex: 0,
-                    vertex_stride: size_of::<Vertex>() as u64,
-                    index_buffer: None,
-                    first_index: None,
-                    transform_buffer: None,
-                    transform_buffer_offset: None,
-                }]),
-            }),
-            std::iter::once(&tlas_package),
+        let normal_depth_texture_view =
+            normal_depth_texture.create_view(&TextureViewDescriptor::default());
+
+        let albedo_texture = device.create_texture(&TextureDescriptor {
+            label: Some("albedo texture"),
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::STORAGE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let albedo_texture_view = albedo_texture.create_view(&TextureViewDescriptor::default());
+
+        let position_texture = device.create_texture(&TextureDescriptor {
+            label: Some("position texture"),
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let position_texture_view = position_texture.create_view(&TextureViewDescriptor::default());
+
+        let id_texture = device.create_texture(&TextureDescriptor {
+            label: Some("id texture"),
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let id_texture_view = id_texture.create_view(&TextureViewDescriptor::default());
+
+        let create_history_texture = |texture: &Texture| {
+            device.create_texture(&TextureDescriptor {
+                label: Some("history texture"),
+                size: texture_size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: texture.format(),
+                usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            })
+        };
+
+        let history_textures = [
+            &accumulation_texture,
+            &variance_texture,
+            &normal_depth_texture,
+            &albedo_texture,
+        ]
+        .map(|texture| (texture.clone(), create_history_texture(texture)));
+
+        let history_texture_views = history_textures
+            .each_ref()
+            .map(|(_, history)| history.create_view(&TextureViewDescriptor::default()));
+
+        let denoiser = Denoiser::new(
+            &device,
+            size,
+            DenoiserInputs {
+                accumulation: &accumulation_texture_view,
+                normal_depth: &normal_depth_texture_view,
+                albedo: &albedo_texture_view,
+                render: &render_texture_view,
+                render_format: texture_format,
+            },
         );
-        queue.submit(std::iter::once(encoder.finish()));
+
+        let material_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("material buffer"),
+            contents: bytemuck::cast_slice(&model.materials),
+            usage: BufferUsages::STORAGE,
+        });
 
         let noise_texture_view = load_noise(&queue, &device);
+        let sampler_tables = create_sampler_tables(&device);
+        let mut entries = vec![
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&render_texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Buffer(camera_buffer.as_entire_buffer_binding()),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::Buffer(vertex_buffer.as_entire_buffer_binding()),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: BindingResource::TextureView(&noise_texture_view),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: BindingResource::TextureView(&skybox_texture_view),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: BindingResource::TextureView(&accumulation_texture_view),
+            },
+            BindGroupEntry {
+                binding: 7,
+                resource: BindingResource::Buffer(sampler_tables.as_entire_buffer_binding()),
+            },
+            BindGroupEntry {
+                binding: 8,
+                resource: BindingResource::Buffer(material_buffer.as_entire_buffer_binding()),
+            },
+            BindGroupEntry {
+                binding: 9,
+                resource: BindingResource::TextureView(&variance_texture_view),
+            },
+            BindGroupEntry {
+                binding: 10,
+                resource: BindingResource::TextureView(&normal_depth_texture_view),
+            },
+            BindGroupEntry {
+                binding: 11,
+                resource: BindingResource::TextureView(&albedo_texture_view),
+            },
+            BindGroupEntry {
+                binding: 12,
+                resource: BindingResource::TextureView(&history_texture_views[0]),
+            },
+            BindGroupEntry {
+                binding: 13,
+                resource: BindingResource::TextureView(&history_texture_views[1]),
+            },
+            BindGroupEntry {
+                binding: 14,
+                resource: BindingResource::TextureView(&history_texture_views[2]),
+            },
+            BindGroupEntry {
+                binding: 15,
+                resource: BindingResource::TextureView(&history_texture_views[3]),
+            },
+            BindGroupEntry {
+                binding: 16,
+                resource: BindingResource::TextureView(&position_texture_view),
+            },
+            BindGroupEntry {
+                binding: 17,
+                resource: BindingResource::TextureView(&id_texture_view),
+            },
+        ];
+        entries.extend(geometry.bind_group_entries());
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: None,
             layout: &bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(&render_texture_view),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Buffer(camera_buffer.as_entire_buffer_binding()),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: tlas_package.as_binding(),
-                },
-                BindGroupEntry {
-                    binding: 3,
-                    resource: BindingResource::Buffer(vertex_buffer.as_entire_buffer_binding()),
-                },
-                BindGroupEntry {
-                    binding: 4,
-                    resource: BindingResource::TextureView(&noise_texture_view),
-                },
-                BindGroupEntry {
-                    binding: 5,
-                    resource: BindingResource::TextureView(&skybox_texture_view),
-                },
-            ],
+            entries: &entries,
         });
 
-        Self {
+        Ok(Self {
             surface,
             device,
             queue,
-            pipeline,
+            pipelines: HashMap::new(),
+            pipeline_layout,
+            shader_files: ShaderFiles::built_in(),
+            defines,
             render_texture,
+            accumulation_texture,
+            normal_depth_texture,
+            albedo_texture,
+            position_texture,
+            id_texture,
             bind_group,
+            denoiser,
+            history_textures,
             camera_buffer,
-            window_size,
+            size,
             num_samples: 0,
+            history: History::Restart,
+            temporal_reprojection: false,
+            camera: None,
+            history_camera: None,
+            sampler: Sampler::Sobol,
+            settings: RenderSettings::default(),
+            sample_heatmap: false,
+            denoise: false,
+            aovs: false,
+            aov_view: None,
+            tile: None,
+            sample_offset: 0,
+        })
+    }
+
+    /// Index of the first sample of the accumulation and restarts it. Renderers with disjoint
+    /// sample ranges of the same image add up to it when weighted by their sample counts.
+    pub fn set_sample_offset(&mut self, sample_offset: u32) {
+        self.sample_offset = sample_offset;
+        self.restart();
+    }
+
+    /// Renders only `tile` of the image the camera sees, whose size has to match the renderer,
+    /// takes effect with the next [`Renderer::update_camera`]. Random sequences depend on the
+    /// position in the whole image, so tiles match the same parts of an image rendered at once.
+    pub fn set_tile(&mut self, tile: Option<Tile>) {
+        assert!(tile.is_none_or(|tile| tile.size == self.size));
+        self.tile = tile;
+    }
+
+    /// Writes the AOVs for [`Renderer::read_aov`] with every [`Renderer::render`]. Albedo,
+    /// normal and depth are always accumulated for the denoiser.
+    pub fn set_aovs(&mut self, aovs: bool) {
+        self.aovs = aovs;
+    }
+
+    /// Shows an AOV instead of the image, takes effect with the next [`Renderer::render`].
+    pub fn set_aov_view(&mut self, aov_view: Option<Aov>) {
+        self.aov_view = aov_view;
+    }
+
+    /// Filters the noise out of the presented image and [`Renderer::read_output`], takes effect
+    /// with the next [`Renderer::render`]. The accumulation itself stays unfiltered.
+    pub fn set_denoise(&mut self, denoise: bool) {
+        self.denoise = denoise;
+    }
+
+    /// Shows the number of samples of every pixel relative to the most sampled pixel instead of
+    /// the image, takes effect with the next [`Renderer::render`].
+    pub fn set_sample_heatmap(&mut self, sample_heatmap: bool) {
+        self.sample_heatmap = sample_heatmap;
+    }
+
+    /// Changes the path tracing parameters and restarts the accumulation.
+    pub fn set_settings(&mut self, settings: RenderSettings) {
+        self.settings = settings;
+        self.restart();
+    }
+
+    /// Switches the sample sequence and restarts the accumulation.
+    pub fn set_sampler(&mut self, sampler: Sampler) {
+        self.sampler = sampler;
+        self.restart();
+    }
+
+    /// Keeps the samples of surfaces that stay visible when the camera changes, by reprojecting
+    /// them with the previous camera and its first hit distances, instead of restarting the
+    /// accumulation. Reprojected pixels are no longer a pure function of the seed, so offline
+    /// renders leave this disabled.
+    pub fn set_temporal_reprojection(&mut self, temporal_reprojection: bool) {
+        self.temporal_reprojection = temporal_reprojection;
+    }
+
+    /// Compiles the path tracer from the shader files in `directory` instead of the ones built
+    /// into the binary and restarts the accumulation. Keeps the current pipelines and returns the
+    /// errors if any variant fails to compile.
+    pub fn reload_shaders(&mut self, directory: &Path) -> Result<(), String> {
+        let shader_files = ShaderFiles::load(directory)?;
+        let mut sources = Vec::new();
+        for variant in PipelineVariant::ALL {
+            let source = variant.compose(&shader_files, &self.defines)?;
+            validate(&source)?;
+            sources.push((variant, source));
+        }
+
+        // Backends can still reject shaders naga accepts, so every variant is built inside the
+        // error scope instead of on demand in `render`.
+        self.device.push_error_scope(ErrorFilter::Validation);
+        let pipelines = sources
+            .iter()
+            .map(|(variant, source)| {
+                let pipeline = create_pipeline(&self.device, &self.pipeline_layout, source);
+                (*variant, pipeline)
+            })
+            .collect();
+        if let Some(err) = pollster::block_on(self.device.pop_error_scope()) {
+            return Err(err.to_string());
+        }
+
+        self.shader_files = shader_files;
+        self.pipelines = pipelines;
+        self.restart();
+        Ok(())
+    }
+
+    /// The pipeline variant the current settings need.
+    fn variant(&self) -> PipelineVariant {
+        PipelineVariant {
+            aovs: self.aovs || self.aov_view.is_some(),
+            debug_views: self.sample_heatmap || self.aov_view.is_some(),
         }
     }
 
-    pub fn update_camera(&mut self, view: &Mat4, projection: &Mat4) {
+    pub fn update_camera(&mut self, camera: &Camera) {
+        // Keep what the current camera accumulated, unless no dispatch has used the history
+        // of an earlier change yet.
+        if self.temporal_reprojection && self.history == History::Continue {
+            self.copy_history();
+            self.history_camera = self.camera;
+        }
+
+        let previous = self.history_camera.unwrap_or(*camera);
+        let reproject = self.temporal_reprojection
+            && self.history != History::Restart
+            && self.history_camera.is_some()
+            && previous.projection == camera.projection
+            && camera.projection.is_planar();
+
+        let projection = |camera: &Camera| match &self.tile {
+            Some(tile) => camera.calculate_tile_projection(tile),
+            None => camera.calculate_projection(&self.size),
+        };
+        let previous_view = previous.calculate_view();
+        let previous_inverse_view = previous_view.inverse();
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::bytes_of(&CameraMatrices {
-                inverse_view: view.inverse(),
-                inverse_proj: projection.inverse(),
+                inverse_view: camera.calculate_view().inverse(),
+                inverse_proj: projection(camera).inverse(),
+                near: camera.near,
+                far: camera.far,
+                projection: camera.projection as u32,
+                eye_separation: camera.eye_separation,
+                previous_view_proj: projection(&previous) * previous_view,
+                previous_eye: previous_inverse_view.transform_point3(Vec3::ZERO),
+                previous_projection: previous.projection as u32,
+                previous_forward: previous_inverse_view.transform_vector3(Vec3::Z),
+                _pad0: 0.0,
+                tile_offset: self.tile.map_or([0, 0], |tile| [tile.x, tile.y]),
+                _pad1: [0, 0],
             }),
         );
+
+        self.camera = Some(*camera);
         self.num_samples = 0;
+        self.history = if reproject {
+            History::Reproject
+        } else {
+            History::Restart
+        };
     }
 
-    pub fn render(&mut self, time: f32) -> Result<u32, SurfaceError> {
-        let surface_texture = self.surface.get_current_texture()?;
+    /// Samples per pixel added since the accumulation last restarted or the camera changed,
+    /// reprojected pixels carry additional samples of earlier cameras.
+    pub fn num_samples(&self) -> u32 {
+        self.num_samples
+    }
+
+    fn restart(&mut self) {
+        self.num_samples = 0;
+        self.history = History::Restart;
+    }
+
+    fn copy_history(&self) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        for (texture, history) in &self.history_textures {
+            encoder.copy_texture_to_texture(
+                texture.as_image_copy(),
+                history.as_image_copy(),
+                texture.size(),
+            );
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Adds `dispatch_samples` samples per pixel in a single dispatch and presents the result,
+    /// zero only presents the current accumulation. The random sequence of every sample is
+    /// determined by `seed` and its index.
+    pub fn render(&mut self, seed: u32, dispatch_samples: u32) -> Result<u32, SurfaceError> {
+        let surface_texture = self
+            .surface
+            .as_ref()
+            .map(Surface::get_current_texture)
+            .transpose()?;
+
+        // Only the built-in shaders build their variants on first use, reloaded shaders build
+        // them all up front.
+        let variant = self.variant();
+        self.pipelines.entry(variant).or_insert_with(|| {
+            let source = variant
+                .compose(&self.shader_files, &self.defines)
+                .expect("the built-in shaders compose");
+            create_pipeline(&self.device, &self.pipeline_layout, &source)
+        });
 
         let mut encoder = self
             .device
@@ -355,34 +948,417 @@ impl Renderer {
             timestamp_writes: None,
         });
 
-        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_pipeline(&self.pipelines[&variant]);
         compute_pass.set_bind_group(0, &self.bind_group, &[]);
         compute_pass.set_push_constants(
             0,
             bytemuck::bytes_of(&PushConstants {
-                time,
+                seed,
                 num_samples: self.num_samples,
+                dispatch_samples,
+                sampler_type: self.sampler as u32,
+                max_depth: self.settings.max_depth,
+                rr_min_depth: self.settings.rr_min_depth,
+                rr_min_probability: self.settings.rr_min_probability,
+                adaptive_threshold: self.settings.adaptive_threshold,
+                adaptive_min_samples: self.settings.adaptive_min_samples,
+                sample_heatmap: self.sample_heatmap as u32,
+                history: self.history as u32,
+                aov_view: self.aov_view.map_or(0, |aov| aov as u32 + 1),
+                sample_offset: self.sample_offset,
             }),
         );
         compute_pass.dispatch_workgroups(
-            self.window_size.width / 10,
-            self.window_size.height / 10,
+            self.size.width.div_ceil(WORKGROUP_SIZE),
+            self.size.height.div_ceil(WORKGROUP_SIZE),
             1,
         );
 
         drop(compute_pass);
 
-        encoder.copy_texture_to_texture(
-            self.render_texture.as_image_copy(),
-            surface_texture.texture.as_image_copy(),
-            surface_texture.texture.size(),
-        );
+        if self.denoise && !self.sample_heatmap && self.aov_view.is_none() {
+            self.denoiser.encode(&mut encoder);
+        }
+
+        if let Some(surface_texture) = &surface_texture {
+            encoder.copy_texture_to_texture(
+                self.render_texture.as_image_copy(),
+                surface_texture.texture.as_image_copy(),
+                surface_texture.texture.size(),
+            );
+        }
 
         self.queue.submit(std::iter::once(encoder.finish()));
-        surface_texture.present();
+        if let Some(surface_texture) = surface_texture {
+            surface_texture.present();
+        }
 
-        self.num_samples += 1;
+        self.num_samples += dispatch_samples;
+        self.history = History::Continue;
 
         Ok(self.num_samples)
     }
+
+    /// Copies the linear radiance back to the cpu, denoised if enabled when it was last rendered.
+    pub fn read_output(&self) -> Rgba32FImage {
+        if self.denoise {
+            self.read_texture(self.denoiser.output_texture())
+        } else {
+            // Opaque alpha instead of the per pixel sample counts of the accumulation.
+            let mut image = self.read_accumulation();
+            for pixel in image.pixels_mut() {
+                pixel.0[3] = 1.0;
+            }
+            image
+        }
+    }
+
+    /// Copies the accumulated linear radiance back to the cpu, with the number of samples of
+    /// every pixel in alpha.
+    pub fn read_accumulation(&self) -> Rgba32FImage {
+        self.read_texture(&self.accumulation_texture)
+    }
+
+    /// Copies an AOV back to the cpu, see [`Renderer::set_aovs`]. Depth and the IDs are
+    /// repeated in the color channels.
+    pub fn read_aov(&self, aov: Aov) -> Rgba32FImage {
+        match aov {
+            Aov::Albedo => self.read_texture(&self.albedo_texture),
+            Aov::Normal | Aov::Depth => {
+                let mut image = self.read_texture(&self.normal_depth_texture);
+                for pixel in image.pixels_mut() {
+                    let [x, y, z, depth] = pixel.0;
+                    pixel.0 = match aov {
+                        Aov::Depth => [depth, depth, depth, 1.0],
+                        _ => [x, y, z, 1.0],
+                    };
+                }
+                image
+            }
+            Aov::Position => self.read_texture(&self.position_texture),
+            Aov::MaterialId | Aov::InstanceId => {
+                let mut image = self.read_texture(&self.id_texture);
+                for pixel in image.pixels_mut() {
+                    let [material_id, instance_id, ..] = pixel.0;
+                    let id = match aov {
+                        Aov::MaterialId => material_id,
+                        _ => instance_id,
+                    };
+                    pixel.0 = [id, id, id, 1.0];
+                }
+                image
+            }
+        }
+    }
+
+    pub fn read_accumulation_state(&self) -> AccumulationState {
+        AccumulationState {
+            size: self.size,
+            num_samples: self.num_samples,
+            textures: self
+                .history_textures
+                .iter()
+                .map(|(texture, _)| bytemuck::pod_collect_to_vec(&self.read_texture_bytes(texture)))
+                .collect(),
+        }
+    }
+
+    /// Continues the accumulation of `state`, which has to match the camera and settings the
+    /// renderer was configured with since.
+    pub fn restore_accumulation(&mut self, state: &AccumulationState) -> Result<(), String> {
+        let texel_counts = self
+            .history_textures
+            .iter()
+            .map(|(texture, _)| {
+                let channels = texture.format().components() as usize;
+                self.size.width as usize * self.size.height as usize * channels
+            })
+            .collect::<Vec<_>>();
+        let lengths = state.textures.iter().map(Vec::len).collect::<Vec<_>>();
+        if state.size != self.size || lengths != texel_counts {
+            return Err(format!(
+                "the checkpoint has {}x{} pixels instead of {}x{}",
+                state.size.width, state.size.height, self.size.width, self.size.height
+            ));
+        }
+
+        for ((texture, _), texels) in self.history_textures.iter().zip(&state.textures) {
+            let texel_size = texture.format().block_copy_size(None).unwrap();
+            self.queue.write_texture(
+                texture.as_image_copy(),
+                bytemuck::cast_slice(texels),
+                TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.size.width * texel_size),
+                    rows_per_image: None,
+                },
+                texture.size(),
+            );
+        }
+
+        self.num_samples = state.num_samples;
+        self.history = History::Continue;
+        Ok(())
+    }
+
+    /// Copies what the window shows back to the cpu, including the AOV views and the sample
+    /// heatmap.
+    pub fn read_display(&self) -> Rgba32FImage {
+        let bgra = self.render_texture.format() == TextureFormat::Bgra8Unorm;
+        let pixels = self
+            .read_texture_bytes(&self.render_texture)
+            .chunks(4)
+            .flat_map(|texel| {
+                if bgra {
+                    [texel[2], texel[1], texel[0], texel[3]]
+                } else {
+                    [texel[0], texel[1], texel[2], texel[3]]
+                }
+            })
+            .map(|channel| channel as f32 / 255.0)
+            .collect();
+        Rgba32FImage::from_raw(self.size.width, self.size.height, pixels).unwrap()
+    }
+
+    fn read_texture(&self, texture: &Texture) -> Rgba32FImage {
+        let pixels = bytemuck::pod_collect_to_vec(&self.read_texture_bytes(texture));
+        Rgba32FImage::from_raw(self.size.width, self.size.height, pixels).unwrap()
+    }
+
+    /// The texels of the texture, without the padding of the rows.
+    fn read_texture_bytes(&self, texture: &Texture) -> Vec<u8> {
+        let texel_size = texture.format().block_copy_size(None).unwrap();
+        let unpadded_bytes_per_row = self.size.width * texel_size;
+        let bytes_per_row = unpadded_bytes_per_row.next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let readback_buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("readback buffer"),
+            size: (bytes_per_row * self.size.height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            TexelCopyBufferInfo {
+                buffer: &readback_buffer,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = readback_buffer.slice(..);
+        buffer_slice.map_async(MapMode::Read, |result| result.unwrap());
+        self.device.poll(Maintain::Wait);
+
+        let mapped = buffer_slice.get_mapped_range();
+        mapped
+            .chunks(bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect()
+    }
+}
+
+/// Feature defines of a pipeline variant of the path tracer, which leave out the work the
+/// current settings do not need.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct PipelineVariant {
+    /// Writes the position and ID textures.
+    aovs: bool,
+    /// Shows the sample heatmap and the AOV views.
+    debug_views: bool,
+}
+
+impl PipelineVariant {
+    const ALL: [Self; 4] = [
+        Self {
+            aovs: false,
+            debug_views: false,
+        },
+        Self {
+            aovs: true,
+            debug_views: false,
+        },
+        Self {
+            aovs: false,
+            debug_views: true,
+        },
+        Self {
+            aovs: true,
+            debug_views: true,
+        },
+    ];
+
+    fn defines(self) -> Vec<&'static str> {
+        [(self.aovs, "AOVS"), (self.debug_views, "DEBUG_VIEWS")]
+            .into_iter()
+            .filter_map(|(enabled, define)| enabled.then_some(define))
+            .collect()
+    }
+
+    /// Composes the path tracer of this variant with the defines of the device.
+    fn compose(self, shader_files: &ShaderFiles, defines: &[&str]) -> Result<String, String> {
+        let defines = [defines, &self.defines()].concat();
+        shader_files.preprocess("shader.wgsl", &defines)
+    }
+}
+
+/// Compiles the path tracer from the composed `shader.wgsl`.
+fn create_pipeline(device: &Device, layout: &PipelineLayout, source: &str) -> ComputePipeline {
+    let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("shader.wgsl"),
+        source: ShaderSource::Wgsl(source.into()),
+    });
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        module: &shader_module,
+        entry_point: Some("render"),
+        compilation_options: Default::default(),
+        cache: None,
+    })
+}
+
+/// The scene geometry at binding 2, which `intersect_closest` finds the closest hits in.
+enum SceneGeometry {
+    AccelerationStructure(TlasPackage),
+    /// Nodes and triangle indices of a [`Bvh`], traversed by `bvh.wgsl` on adapters without ray
+    /// queries.
+    Bvh {
+        nodes: Buffer,
+        triangles: Buffer,
+    },
+}
+
+impl SceneGeometry {
+    fn new(device: &Device, queue: &Queue, model: &Model, vertex_buffer: &Buffer) -> Self {
+        if !device.features().contains(RAY_QUERY_FEATURES) {
+            let mut bvh = Bvh::build(&model.vertices);
+            log::info!(
+                "No hardware ray queries, traversing a bvh of {} nodes",
+                bvh.nodes.len()
+            );
+            // Bindings cannot be empty.
+            if bvh.triangles.is_empty() {
+                bvh.triangles.push(0);
+            }
+            let create_buffer = |label, contents| {
+                device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some(label),
+                    contents,
+                    usage: BufferUsages::STORAGE,
+                })
+            };
+            return Self::Bvh {
+                nodes: create_buffer("bvh nodes", bytemuck::cast_slice(&bvh.nodes)),
+                triangles: create_buffer("bvh triangles", bytemuck::cast_slice(&bvh.triangles)),
+            };
+        }
+
+        let tlas = device.create_tlas(&CreateTlasDescriptor {
+            label: None,
+            max_instances: 1,
+            flags: AccelerationStructureFlags::PREFER_FAST_TRACE,
+            update_mode: AccelerationStructureUpdateMode::Build,
+        });
+
+        let geometry_size = BlasTriangleGeometrySizeDescriptor {
+            vertex_format: VertexFormat::Float32x3,
+            vertex_count: model.vertices.len() as u32,
+            index_format: None,
+            index_count: None,
+            flags: AccelerationStructureGeometryFlags::OPAQUE,
+        };
+        let blas = device.create_blas(
+            &CreateBlasDescriptor {
+                label: None,
+                flags: AccelerationStructureFlags::PREFER_FAST_TRACE,
+                update_mode: AccelerationStructureUpdateMode::Build,
+            },
+            BlasGeometrySizeDescriptors::Triangles {
+                descriptors: vec![geometry_size.clone()],
+            },
+        );
+
+        let tlas_package = TlasPackage::new_with_instances(
+            tlas,
+            vec![Some(TlasInstance::new(
+                &blas,
+                Mat4::IDENTITY.to_cols_array()[..12].try_into().unwrap(),
+                0,
+                0xff,
+            ))],
+        );
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.build_acceleration_structures(
+            std::iter::once(&BlasBuildEntry {
+                blas: &blas,
+                geometry: BlasGeometries::TriangleGeometries(vec![BlasTriangleGeometry {
+                    size: &geometry_size,
+                    vertex_buffer,
+                    first_vertex: 0,
+                    vertex_stride: size_of::<Vertex>() as u64,
+                    index_buffer: None,
+                    first_index: None,
+                    transform_buffer: None,
+                    transform_buffer_offset: None,
+                }]),
+            }),
+            std::iter::once(&tlas_package),
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Self::AccelerationStructure(tlas_package)
+    }
+
+    fn layout_entries(&self) -> Vec<BindGroupLayoutEntry> {
+        let storage_buffer = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        match self {
+            Self::AccelerationStructure(_) => vec![BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::AccelerationStructure,
+                count: None,
+            }],
+            Self::Bvh { .. } => vec![storage_buffer(2), storage_buffer(18)],
+        }
+    }
+
+    fn bind_group_entries(&self) -> Vec<BindGroupEntry> {
+        match self {
+            Self::AccelerationStructure(tlas_package) => vec![BindGroupEntry {
+                binding: 2,
+                resource: tlas_package.as_binding(),
+            }],
+            Self::Bvh { nodes, triangles } => vec![
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Buffer(nodes.as_entire_buffer_binding()),
+                },
+                BindGroupEntry {
+                    binding: 18,
+                    resource: BindingResource::Buffer(triangles.as_entire_buffer_binding()),
+                },
+            ],
+        }
+    }
 }
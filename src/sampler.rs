@@ -0,0 +1,275 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{UVec2, Vec2};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    Buffer, BufferUsages, Device,
+};
+
+const SOBOL_DIMENSIONS: usize = 2;
+const SOBOL_BITS: usize = 32;
+const NUM_PRIMES: usize = 32;
+
+/// Source of the sample points of every pixel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sampler {
+    /// Blue noise textures at random offsets, white noise across samples.
+    BlueNoise,
+    /// Owen scrambled Sobol points, padded with a shuffled sequence per dimension.
+    Sobol,
+    /// Halton points, decorrelated between pixels by random rotations. Dimensions past the
+    /// 16 that the prime bases cover use Sobol points.
+    Halton,
+}
+
+impl Sampler {
+    pub const ALL: [Self; 3] = [Self::BlueNoise, Self::Sobol, Self::Halton];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::BlueNoise => "blue-noise",
+            Self::Sobol => "sobol",
+            Self::Halton => "halton",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|sampler| sampler.name() == name)
+    }
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|s| *s == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Sobol generator matrices and Halton bases, laid out like `SamplerTables` in the shader.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct SamplerTables {
+    sobol: [[u32; SOBOL_BITS]; SOBOL_DIMENSIONS],
+    primes: [u32; NUM_PRIMES],
+}
+
+/// Primitive polynomial degree, coefficients and initial direction numbers of the Sobol
+/// dimensions after the first, from Joe and Kuo's `new-joe-kuo-6.21201`.
+const JOE_KUO: [(usize, u32, &[u32]); SOBOL_DIMENSIONS - 1] = [(1, 0, &[1])];
+
+impl SamplerTables {
+    pub fn generate() -> Self {
+        let mut sobol = [[0; SOBOL_BITS]; SOBOL_DIMENSIONS];
+
+        // The first dimension is the van der Corput sequence.
+        for (bit, direction) in sobol[0].iter_mut().enumerate() {
+            *direction = 1 << (31 - bit);
+        }
+        for (directions, (degree, coefficients, initial)) in sobol[1..].iter_mut().zip(JOE_KUO) {
+            for bit in 0..SOBOL_BITS {
+                directions[bit] = if bit < degree {
+                    initial[bit] << (31 - bit)
+                } else {
+                    let mut direction =
+                        directions[bit - degree] ^ (directions[bit - degree] >> degree);
+                    for k in 1..degree {
+                        if (coefficients >> (degree - 1 - k)) & 1 != 0 {
+                            direction ^= directions[bit - k];
+                        }
+                    }
+                    direction
+                };
+            }
+        }
+
+        let mut primes = [0; NUM_PRIMES];
+        let mut candidate = 2;
+        for prime in &mut primes {
+            while (2..candidate).any(|divisor| candidate % divisor == 0) {
+                candidate += 1;
+            }
+            *prime = candidate;
+            candidate += 1;
+        }
+
+        Self { sobol, primes }
+    }
+
+    fn sobol(&self, index: u32, dimension: usize) -> u32 {
+        (0..SOBOL_BITS)
+            .filter(|bit| (index >> bit) & 1 != 0)
+            .fold(0, |x, bit| x ^ self.sobol[dimension][bit])
+    }
+
+    /// 2D Sobol point `index` of the sequence selected by `seed`, following Burley's
+    /// "Practical Hash-based Owen Scrambling": the index is shuffled and both dimensions are
+    /// Owen scrambled, so every sample dimension gets its own decorrelated sequence.
+    pub fn sobol_2d(&self, index: u32, seed: u32) -> Vec2 {
+        let shuffled = nested_uniform_scramble(index, hash(seed));
+        Vec2::new(
+            unit_float(nested_uniform_scramble(
+                self.sobol(shuffled, 0),
+                hash_combine(seed, 0),
+            )),
+            unit_float(nested_uniform_scramble(
+                self.sobol(shuffled, 1),
+                hash_combine(seed, 1),
+            )),
+        )
+    }
+
+    /// 2D Halton point `index` of sample `dimension`, rotated by a random offset derived from
+    /// `seed`.
+    ///
+    /// The prime table covers the first `NUM_PRIMES / 2` dimensions. Reusing its bases for
+    /// deeper dimensions would repeat earlier dimensions up to the rotation, so those fall back
+    /// to the scrambled Sobol points of [`Self::sobol_2d`].
+    pub fn halton_2d(&self, index: u32, dimension: u32, seed: u32) -> Vec2 {
+        if dimension as usize >= NUM_PRIMES / 2 {
+            return self.sobol_2d(index, seed);
+        }
+        let base = |offset| self.primes[2 * dimension as usize + offset];
+        let rotation = Vec2::new(
+            unit_float(hash_combine(seed, 0)),
+            unit_float(hash_combine(seed, 1)),
+        );
+        (Vec2::new(
+            radical_inverse(index, base(0)),
+            radical_inverse(index, base(1)),
+        ) + rotation)
+            .fract()
+    }
+}
+
+pub fn create_sampler_tables(device: &Device) -> Buffer {
+    device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("sampler tables"),
+        contents: bytemuck::bytes_of(&SamplerTables::generate()),
+        usage: BufferUsages::STORAGE,
+    })
+}
+
+/// Seed of one sample dimension of one pixel, matching `dimension_seed` in the shader.
+pub fn dimension_seed(seed: u32, gid: UVec2, dimension: u32) -> u32 {
+    hash_combine(hash_combine(hash_combine(seed, gid.x), gid.y), dimension)
+}
+
+/// Wang hash, matching `hash` in the shader.
+pub fn hash(value: u32) -> u32 {
+    let mut state = value;
+    state = (state ^ 61) ^ (state >> 16);
+    state = state.wrapping_mul(9);
+    state ^= state >> 4;
+    state = state.wrapping_mul(0x27d4eb2d);
+    state ^= state >> 15;
+    state
+}
+
+fn hash_combine(seed: u32, value: u32) -> u32 {
+    hash(seed ^ hash(value))
+}
+
+/// Owen scrambling of the bits of `x` with the Laine-Karras permutation.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> f32 {
+    let inverse_base = 1.0 / base as f32;
+    let mut fraction = inverse_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f32 * fraction;
+        index /= base;
+        fraction *= inverse_base;
+    }
+    result
+}
+
+/// Maps the upper 24 bits to [0, 1), the precision of an f32 mantissa.
+fn unit_float(x: u32) -> f32 {
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sobol_points_are_stratified() {
+        let tables = SamplerTables::generate();
+        for seed in [0, 1, 1234] {
+            // Every 2^k points of a (0, 2)-sequence cover each cell of a 2^i x 2^(k-i) grid once.
+            let points = (0..16)
+                .map(|index| tables.sobol_2d(index, seed))
+                .collect::<Vec<_>>();
+            for columns in [1, 2, 4, 8, 16] {
+                let rows = 16 / columns;
+                let mut cells = points
+                    .iter()
+                    .map(|p| (p.x * columns as f32) as u32 * rows + (p.y * rows as f32) as u32)
+                    .collect::<Vec<_>>();
+                cells.sort();
+                cells.dedup();
+                assert_eq!(cells.len(), 16, "{columns}x{rows} grid, seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn halton_uses_consecutive_primes() {
+        let tables = SamplerTables::generate();
+        assert_eq!(tables.primes[..8], [2, 3, 5, 7, 11, 13, 17, 19]);
+        assert_eq!(radical_inverse(6, 2), 0.375);
+    }
+
+    #[test]
+    fn halton_dimensions_past_the_prime_table_are_not_shifted_copies() {
+        let tables = SamplerTables::generate();
+        let dimension = NUM_PRIMES as u32 / 2;
+        let shifts = (0..16)
+            .map(|index| {
+                let first = tables.halton_2d(index, 0, dimension_seed(0, UVec2::ZERO, 0));
+                let deeper =
+                    tables.halton_2d(index, dimension, dimension_seed(0, UVec2::ZERO, dimension));
+                (deeper - first).rem_euclid(Vec2::ONE)
+            })
+            .collect::<Vec<_>>();
+        assert!(shifts.iter().any(|shift| shift.distance(shifts[0]) > 1e-3));
+    }
+
+    #[test]
+    fn low_discrepancy_samplers_converge_faster() {
+        let tables = SamplerTables::generate();
+        // Integral of x * y over the unit square is 1/4.
+        let error = |sample: &dyn Fn(u32, u32) -> Vec2| {
+            let num_pixels = 256;
+            let squared_error = (0..num_pixels)
+                .map(|pixel| {
+                    let seed = dimension_seed(0, UVec2::new(pixel, 0), 1);
+                    let estimate = (0..64)
+                        .map(|index| {
+                            let p = sample(index, seed);
+                            p.x * p.y
+                        })
+                        .sum::<f32>()
+                        / 64.0;
+                    (estimate - 0.25).powi(2)
+                })
+                .sum::<f32>();
+            (squared_error / num_pixels as f32).sqrt()
+        };
+
+        let random = error(&|index, seed| {
+            let x = hash_combine(seed, index);
+            Vec2::new(unit_float(x), unit_float(hash(x)))
+        });
+        let sobol = error(&|index, seed| tables.sobol_2d(index, seed));
+        let halton = error(&|index, seed| tables.halton_2d(index, 1, seed));
+
+        assert!(sobol < random / 4.0, "sobol {sobol}, random {random}");
+        assert!(halton < random / 2.0, "halton {halton}, random {random}");
+    }
+}
@@ -0,0 +1,87 @@
+//! Watches the shader sources for changes while developing, so edits take effect without
+//! rebuilding the binary. Polls the modification times, which needs no platform specific file
+//! notifications.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Directory of the shader sources of this build.
+pub const SHADER_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+/// Time between two checks of the modification times.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct ShaderWatcher {
+    directory: PathBuf,
+    /// Modification times of the `.wgsl` files in the directory.
+    files: BTreeMap<PathBuf, SystemTime>,
+}
+
+impl ShaderWatcher {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
+        let files = shader_files(&directory);
+        Self { directory, files }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Whether any file changed, appeared or disappeared since the last call.
+    pub fn poll(&mut self) -> bool {
+        let files = shader_files(&self.directory);
+        let changed = files != self.files;
+        self.files = files;
+        changed
+    }
+}
+
+fn shader_files(directory: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return BTreeMap::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let modified = std::fs::metadata(&path).ok()?.modified().ok()?;
+            path.extension()
+                .is_some_and(|extension| extension == "wgsl")
+                .then_some((path, modified))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn detects_modified_files() {
+        let directory = std::env::temp_dir().join("raytracer-shader-watcher-test");
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = File::create(directory.join("shader.wgsl")).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+
+        let mut watcher = ShaderWatcher::new(&directory);
+        assert!(!watcher.poll());
+
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1))
+            .unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        File::create(directory.join("notes.txt")).unwrap();
+        assert!(!watcher.poll());
+        File::create(directory.join("module.wgsl")).unwrap();
+        assert!(watcher.poll());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(watcher.poll());
+    }
+}
@@ -0,0 +1,46 @@
+// Scattering of the materials.
+
+#include "common.wgsl"
+#include "sampling.wgsl"
+
+const MATERIAL_DIFFUSE: u32 = 0;
+const MATERIAL_MIRROR: u32 = 1;
+
+struct BsdfSample {
+  dir: vec3f,
+  // Throughput of the scattered ray, the BSDF times the cosine over the pdf of its direction.
+  weight: vec3f,
+}
+
+// Direction around the normal with a density proportional to the cosine of its angle to the
+// normal, using Malley's method and the orthonormal basis of Duff et al.
+fn cosine_sample_hemisphere(normal: vec3f, sample: vec2f) -> vec3f {
+  let r = sqrt(sample.x);
+  let phi = 2.0 * PI * sample.y;
+  let local = vec3f(r * cos(phi), r * sin(phi), sqrt(max(0.0, 1.0 - sample.x)));
+
+  let sign = select(-1.0, 1.0, normal.z >= 0.0);
+  let a = -1.0 / (sign + normal.z);
+  let b = normal.x * normal.y * a;
+  let tangent = vec3f(1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x);
+  let bitangent = vec3f(b, sign + normal.y * normal.y * a, -normal.y);
+
+  return normalize(local.x * tangent + local.y * bitangent + local.z * normal);
+}
+
+
+// Scatters a ray arriving in `dir` at a surface with the given shading normal. Mirrors draw no
+// samples, which keeps the sample dimensions of diffuse bounces the same.
+fn sample_bsdf(material: Material, normal: vec3f, dir: vec3f, gid: vec3u, dimension: u32) -> BsdfSample {
+  if material.kind == MATERIAL_MIRROR {
+    return BsdfSample(reflect(dir, normal), vec3f(1, 1, 1));
+  }
+  // The cosine and 1/pi of the Lambertian BRDF cancel with the pdf of the sampled direction,
+  // which leaves the albedo as the weight.
+  return BsdfSample(cosine_sample_hemisphere(normal, sample_2d(gid, dimension)), material.albedo);
+}
+
+// Albedo of the first hit for the denoiser, one for mirrors, which keep the reflected colors.
+fn surface_albedo(material: Material) -> vec3f {
+  return select(material.albedo, vec3f(1, 1, 1), material.kind == MATERIAL_MIRROR);
+}
@@ -0,0 +1,120 @@
+// Closest hit of a ray with the bounding volume hierarchy built by `Bvh::build`, included on
+// adapters without ray queries. Follows `Bvh::intersect` and fills the kind, distance, primitive
+// index and barycentrics of the committed ray query intersection it replaces, the single instance
+// has index zero.
+
+#include "common.wgsl"
+
+struct BvhNode {
+  min: vec3f,
+  // Index of the left child for inner nodes, of the first triangle for leaves.
+  left_or_first: u32,
+  max: vec3f,
+  // Number of triangles in a leaf, zero for inner nodes.
+  count: u32,
+}
+
+@group(0) @binding(2)
+var<storage, read> bvh_nodes: array<BvhNode>;
+
+// Triangle indices referenced by the leaves.
+@group(0) @binding(18)
+var<storage, read> bvh_triangles: array<u32>;
+
+// Matches `MAX_DEPTH` in bvh.rs, whose trees never fill the stack.
+const BVH_MAX_DEPTH: u32 = 64;
+const BVH_MISS: f32 = 3.4e38;
+
+fn intersect_closest(ray: RayDesc) -> RayIntersection {
+  var closest: RayIntersection;
+  closest.kind = RAY_QUERY_INTERSECTION_NONE;
+  closest.t = ray.tmax;
+  let inverse_dir = 1.0 / ray.dir;
+
+  var stack: array<u32, BVH_MAX_DEPTH>;
+  var stack_size = 1u;
+  stack[0] = 0u;
+
+  while stack_size > 0 {
+    stack_size -= 1;
+    let node = bvh_nodes[stack[stack_size]];
+
+    // Children follow their parent, so only the root of an empty model has neither triangles
+    // nor a left child past index zero.
+    if node.count > 0 || node.left_or_first == 0 {
+      for (var i = node.left_or_first; i < node.left_or_first + node.count; i++) {
+        intersect_triangle(bvh_triangles[i], ray, &closest);
+      }
+      continue;
+    }
+
+    let left = node.left_or_first;
+    let right = left + 1;
+    let left_t = intersect_node(bvh_nodes[left], ray, inverse_dir, closest.t);
+    let right_t = intersect_node(bvh_nodes[right], ray, inverse_dir, closest.t);
+
+    // Push the farther child first, so the nearer one is visited next.
+    var near = left;
+    var far = right;
+    if right_t < left_t {
+      near = right;
+      far = left;
+    }
+    if max(left_t, right_t) < BVH_MISS && stack_size < BVH_MAX_DEPTH {
+      stack[stack_size] = far;
+      stack_size += 1;
+    }
+    if min(left_t, right_t) < BVH_MISS && stack_size < BVH_MAX_DEPTH {
+      stack[stack_size] = near;
+      stack_size += 1;
+    }
+  }
+
+  return closest;
+}
+
+// Slab test, returns the entry distance or `BVH_MISS`.
+fn intersect_node(node: BvhNode, ray: RayDesc, inverse_dir: vec3f, t_max: f32) -> f32 {
+  let t0 = (node.min - ray.origin) * inverse_dir;
+  let t1 = (node.max - ray.origin) * inverse_dir;
+  let near = min(t0, t1);
+  let far = max(t0, t1);
+  let t_enter = max(max(near.x, near.y), max(near.z, ray.tmin));
+  let t_exit = min(min(far.x, far.y), min(far.z, t_max));
+
+  return select(BVH_MISS, t_enter, t_enter <= t_exit);
+}
+
+// Moller-Trumbore, replaces `closest` if the triangle is hit before it.
+fn intersect_triangle(triangle: u32, ray: RayDesc, closest: ptr<function, RayIntersection>) {
+  let p0 = vertices[triangle * 3 + 0].position;
+  let edge1 = vertices[triangle * 3 + 1].position - p0;
+  let edge2 = vertices[triangle * 3 + 2].position - p0;
+
+  let p = cross(ray.dir, edge2);
+  let determinant = dot(edge1, p);
+  if abs(determinant) < 1.1920929e-7 {
+    return;
+  }
+  let inverse_determinant = 1.0 / determinant;
+
+  let s = ray.origin - p0;
+  let u = dot(s, p) * inverse_determinant;
+  if u < 0.0 || u > 1.0 {
+    return;
+  }
+
+  let q = cross(s, edge1);
+  let v = dot(ray.dir, q) * inverse_determinant;
+  if v < 0.0 || u + v > 1.0 {
+    return;
+  }
+
+  let t = dot(edge2, q) * inverse_determinant;
+  if t > ray.tmin && t < (*closest).t {
+    (*closest).kind = RAY_QUERY_INTERSECTION_TRIANGLE;
+    (*closest).t = t;
+    (*closest).primitive_index = triangle;
+    (*closest).barycentrics = vec2f(u, v);
+  }
+}
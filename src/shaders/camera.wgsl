@@ -0,0 +1,71 @@
+// Primary rays of the camera projections.
+
+#include "common.wgsl"
+
+const PROJECTION_PERSPECTIVE: u32 = 0;
+const PROJECTION_ORTHOGRAPHIC: u32 = 1;
+const PROJECTION_EQUIRECTANGULAR: u32 = 2;
+const PROJECTION_STEREO_EQUIRECTANGULAR: u32 = 3;
+
+// Pixel coordinates to normalized device coordinates, with pixel centers at whole numbers.
+fn pixel_to_ndc(pixel: vec2f, size: vec2f) -> vec2f {
+  return vec2f(
+    pixel.x / size.x * 2.0 - 1.0,
+    1.0 - pixel.y / size.y * 2.0
+  );
+}
+
+fn primary_ray(ndc: vec2f) -> RayDesc {
+  var origin_view_space = vec3f(0, 0, 0);
+  var direction_view_space: vec3f;
+
+  switch camera.projection {
+    case PROJECTION_ORTHOGRAPHIC: {
+      origin_view_space = vec3(
+        (camera.inverse_proj * vec4(ndc, 0.0, 1.0)).xy,
+        0.0
+      );
+      direction_view_space = vec3f(0, 0, 1);
+    }
+    case PROJECTION_EQUIRECTANGULAR, PROJECTION_STEREO_EQUIRECTANGULAR: {
+      // The projection matrix is the identity, or moves the tile of a tiled render into place.
+      let image_ndc = (camera.inverse_proj * vec4(ndc, 0.0, 1.0)).xy;
+      var panorama = image_ndc;
+      var eye = 0.0;
+      if camera.projection == PROJECTION_STEREO_EQUIRECTANGULAR {
+        // Left eye in the top half, right eye in the bottom half.
+        eye = select(0.5, -0.5, image_ndc.y > 0.0);
+        if image_ndc.y > 0.0 {
+          panorama.y = image_ndc.y * 2.0 - 1.0;
+        } else {
+          panorama.y = image_ndc.y * 2.0 + 1.0;
+        }
+      }
+
+      let longitude = panorama.x * PI;
+      let latitude = panorama.y * PI / 2.0;
+      direction_view_space = vec3f(
+        cos(latitude) * sin(longitude),
+        sin(latitude),
+        cos(latitude) * cos(longitude)
+      );
+      // Omni-directional stereo: offset each eye sideways relative to the viewing direction.
+      origin_view_space = eye * camera.eye_separation * vec3f(cos(longitude), 0, -sin(longitude));
+    }
+    default: {
+      direction_view_space = normalize((camera.inverse_proj * vec4(ndc, 0.0, 1.0)).xyz);
+    }
+  }
+
+  let origin_world_space = camera.inverse_view * vec4(origin_view_space, 1);
+  let direction_world_space = camera.inverse_view * vec4(direction_view_space, 0);
+
+  return RayDesc(
+    0,
+    0xff,
+    camera.near,
+    camera.far,
+    origin_world_space.xyz,
+    normalize(direction_world_space.xyz)
+  );
+}
@@ -0,0 +1,125 @@
+// Bindings, structs and constants shared by the modules of the path tracer.
+//
+// `RGBA8_RENDER_TEXTURE` renders to rgba instead of bgra, for adapters without storage access to
+// bgra textures.
+
+@group(0) @binding(0)
+#ifdef RGBA8_RENDER_TEXTURE
+var render_texture: texture_storage_2d<rgba8unorm, read_write>;
+#else
+var render_texture: texture_storage_2d<bgra8unorm, read_write>;
+#endif
+
+@group(0) @binding(1)
+var<uniform> camera: CameraMatrices;
+
+// Binding 2 holds the scene geometry `intersect_closest` traverses, declared by `ray_query.wgsl`
+// or `bvh.wgsl`.
+
+@group(0) @binding(3)
+var<storage, read> vertices: array<Vertex>;
+
+@group(0) @binding(4)
+var noise_array: texture_storage_2d_array<rgba32float, read>;
+
+@group(0) @binding(5)
+var skybox_texture: texture_storage_2d<rgba32float, read>;
+
+@group(0) @binding(6)
+var accumulation_texture: texture_storage_2d<rgba32float, read_write>;
+
+@group(0) @binding(7)
+var<storage, read> sampler_tables: SamplerTables;
+
+@group(0) @binding(8)
+var<storage, read> materials: array<Material>;
+
+// Sum of squared deviations of the sample luminances from their mean, per pixel.
+@group(0) @binding(9)
+var variance_texture: texture_storage_2d<r32float, read_write>;
+
+// Mean first hit normal and distance, zero for the sky, guides the denoiser.
+@group(0) @binding(10)
+var normal_depth_texture: texture_storage_2d<rgba32float, read_write>;
+
+// Mean first hit albedo, one for the sky.
+@group(0) @binding(11)
+var albedo_texture: texture_storage_2d<rgba32float, read_write>;
+
+// Copies of the accumulation, variance, normal depth and albedo textures seen by the previous
+// camera, reprojected after a camera change.
+@group(0) @binding(12)
+var history_accumulation_texture: texture_storage_2d<rgba32float, read>;
+
+@group(0) @binding(13)
+var history_variance_texture: texture_storage_2d<r32float, read>;
+
+@group(0) @binding(14)
+var history_normal_depth_texture: texture_storage_2d<rgba32float, read>;
+
+@group(0) @binding(15)
+var history_albedo_texture: texture_storage_2d<rgba32float, read>;
+
+// World position and material and instance ID at the pixel center, written with `AOVS`.
+@group(0) @binding(16)
+var position_texture: texture_storage_2d<rgba32float, write>;
+
+@group(0) @binding(17)
+var id_texture: texture_storage_2d<rgba32float, write>;
+
+var<push_constant> push_constants: PushConstants;
+
+const PI: f32 = 3.14159265359;
+
+const NUM_PRIMES: u32 = 32;
+
+const SKY_ID: f32 = -1.0;
+const GROUND_ID: f32 = -2.0;
+
+struct CameraMatrices {
+  inverse_proj: mat4x4<f32>,
+  inverse_view: mat4x4<f32>,
+  near: f32,
+  far: f32,
+  projection: u32,
+  eye_separation: f32,
+  previous_view_proj: mat4x4<f32>,
+  previous_eye: vec3f,
+  previous_projection: u32,
+  previous_forward: vec3f,
+  // Position of the rendered tile in the whole image, see `Renderer::set_tile`.
+  tile_offset: vec2u,
+}
+
+struct PushConstants {
+  seed: u32,
+  num_samples: u32,
+  dispatch_samples: u32,
+  sampler_type: u32,
+  max_depth: u32,
+  rr_min_depth: u32,
+  rr_min_probability: f32,
+  adaptive_threshold: f32,
+  adaptive_min_samples: u32,
+  sample_heatmap: u32,
+  history: u32,
+  aov_view: u32,
+  sample_offset: u32,
+}
+
+struct SamplerTables {
+  sobol: array<array<u32, 32>, 2>,
+  primes: array<u32, NUM_PRIMES>,
+}
+
+struct Vertex {
+  position: vec3f,
+  _pad0: f32,
+  normal: vec3f,
+  material: u32,
+}
+
+struct Material {
+  albedo: vec3f,
+  kind: u32,
+}
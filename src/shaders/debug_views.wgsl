@@ -0,0 +1,78 @@
+// False color views of the AOVs and the samples per pixel, shown instead of the image.
+
+#include "common.wgsl"
+#include "sampling.wgsl"
+
+// AOV shown instead of the image, matching `Aov` plus one.
+const AOV_ALBEDO: u32 = 1;
+const AOV_NORMAL: u32 = 2;
+const AOV_DEPTH: u32 = 3;
+const AOV_POSITION: u32 = 4;
+const AOV_MATERIAL_ID: u32 = 5;
+const AOV_INSTANCE_ID: u32 = 6;
+
+// Distance over which the depth view fades from white to black.
+const DEPTH_VIEW_DISTANCE: f32 = 10.0;
+
+// A distinct color for every ID, black for the sky.
+fn id_color(id: f32) -> vec3f {
+  if id == SKY_ID {
+    return vec3f(0, 0, 0);
+  }
+  let hashed = hash(bitcast<u32>(id));
+  return vec3f(vec3u(hashed, hashed >> 8, hashed >> 16) & vec3u(0xff)) / 255.0;
+}
+
+// Black over purple, red and yellow to white, for values in [0, 1].
+fn heatmap(value: f32) -> vec3f {
+  let stops = array(
+    vec3f(0.0, 0.0, 0.0),
+    vec3f(0.5, 0.0, 0.5),
+    vec3f(1.0, 0.0, 0.0),
+    vec3f(1.0, 1.0, 0.0),
+    vec3f(1.0, 1.0, 1.0),
+  );
+  let position = clamp(value, 0.0, 1.0) * 4.0;
+  let index = min(u32(position), 3u);
+  return mix(stops[index], stops[index + 1], position - f32(index));
+}
+
+// What the sample heatmap or the AOV view shows instead of `color`, see
+// `Renderer::set_sample_heatmap` and `Renderer::set_aov_view`.
+fn debug_view_color(
+  color: vec3f,
+  pixel_samples: u32,
+  albedo: vec3f,
+  normal_depth: vec4f,
+  position: vec4f,
+  ids: vec2f
+) -> vec3f {
+  if push_constants.sample_heatmap != 0 {
+    let max_samples = push_constants.num_samples + push_constants.dispatch_samples;
+    return heatmap(f32(pixel_samples) / f32(max(max_samples, 1u)));
+  }
+  switch push_constants.aov_view {
+    case AOV_ALBEDO: {
+      return albedo;
+    }
+    case AOV_NORMAL: {
+      return normal_depth.xyz * 0.5 + 0.5;
+    }
+    case AOV_DEPTH: {
+      return vec3f(select(0.0, exp(-normal_depth.w / DEPTH_VIEW_DISTANCE), normal_depth.w > 0.0));
+    }
+    case AOV_POSITION: {
+      // Repeats every unit, which shows the world grid.
+      return fract(position.xyz) * position.w;
+    }
+    case AOV_MATERIAL_ID: {
+      return id_color(ids.x);
+    }
+    case AOV_INSTANCE_ID: {
+      return id_color(ids.y);
+    }
+    default: {
+      return color;
+    }
+  }
+}
@@ -0,0 +1,113 @@
+// Edge-avoiding A-Trous wavelet filter (Dammertz et al. 2010) over the accumulated image.
+//
+// The first iteration divides the color by the first hit albedo, so only the noisy illumination
+// is blurred, and `resolve` multiplies the albedo back in. Every iteration doubles the distance
+// between the taps of its 5x5 kernel, the normal, depth and color differences to the center
+// pixel keep the filter from blurring across edges.
+
+@group(0) @binding(0)
+var input_texture: texture_storage_2d<rgba32float, read>;
+
+@group(0) @binding(1)
+var output_texture: texture_storage_2d<rgba32float, write>;
+
+@group(0) @binding(2)
+var normal_depth_texture: texture_storage_2d<rgba32float, read>;
+
+@group(0) @binding(3)
+var albedo_texture: texture_storage_2d<rgba32float, read>;
+
+@group(0) @binding(4)
+#ifdef RGBA8_RENDER_TEXTURE
+var render_texture: texture_storage_2d<rgba8unorm, write>;
+#else
+var render_texture: texture_storage_2d<bgra8unorm, write>;
+#endif
+
+var<push_constant> push_constants: PushConstants;
+
+struct PushConstants {
+  step_size: u32,
+  demodulate: u32,
+}
+
+const NORMAL_POWER: f32 = 128.0;
+const DEPTH_SIGMA: f32 = 0.05;
+const COLOR_SIGMA: f32 = 0.5;
+const MIN_ALBEDO: f32 = 0.001;
+
+fn load_illumination(position: vec2i) -> vec3f {
+  let color = textureLoad(input_texture, position).rgb;
+  if push_constants.demodulate != 0 {
+    return color / max(textureLoad(albedo_texture, position).rgb, vec3f(MIN_ALBEDO));
+  }
+  return color;
+}
+
+fn normal_weight(center: vec3f, tap: vec3f) -> f32 {
+  let center_length = length(center);
+  let tap_length = length(tap);
+  // Zero normals mark the sky, which only blends with itself.
+  if center_length == 0.0 || tap_length == 0.0 {
+    return select(0.0, 1.0, center_length == tap_length);
+  }
+  return pow(max(dot(center / center_length, tap / tap_length), 0.0), NORMAL_POWER);
+}
+
+@compute
+@workgroup_size(10, 10, 1)
+fn atrous(@builtin(global_invocation_id) gid: vec3u) {
+  let size = vec2i(textureDimensions(input_texture));
+  let center = vec2i(gid.xy);
+  if any(center >= size) {
+    return;
+  }
+
+  let kernel = array(1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+  let step = i32(push_constants.step_size);
+
+  let center_color = load_illumination(center);
+  let center_normal_depth = textureLoad(normal_depth_texture, center);
+  // Finer color differences stop the filter as the image gets smoother with every iteration.
+  let color_sigma = COLOR_SIGMA / f32(push_constants.step_size);
+
+  var color_sum = vec3f(0, 0, 0);
+  var weight_sum = 0.0;
+  for (var y = -2; y <= 2; y++) {
+    for (var x = -2; x <= 2; x++) {
+      let position = clamp(center + vec2i(x, y) * step, vec2i(0, 0), size - 1);
+      let color = load_illumination(position);
+      let normal_depth = textureLoad(normal_depth_texture, position);
+
+      let color_difference = center_color - color;
+      let depth_difference = abs(center_normal_depth.w - normal_depth.w);
+      let depth_scale = DEPTH_SIGMA * f32(step) * max(center_normal_depth.w, 1.0);
+
+      let weight = kernel[x + 2] * kernel[y + 2]
+        * normal_weight(center_normal_depth.xyz, normal_depth.xyz)
+        * exp(-depth_difference / depth_scale)
+        * exp(-dot(color_difference, color_difference) / (color_sigma * color_sigma));
+
+      color_sum += color * weight;
+      weight_sum += weight;
+    }
+  }
+
+  // The center tap always contributes, which keeps the weight sum positive.
+  textureStore(output_texture, center, vec4(color_sum / weight_sum, 1.0));
+}
+
+// Multiplies the filtered illumination with the albedo again and shows the result.
+@compute
+@workgroup_size(10, 10, 1)
+fn resolve(@builtin(global_invocation_id) gid: vec3u) {
+  let position = vec2i(gid.xy);
+  if any(position >= vec2i(textureDimensions(input_texture))) {
+    return;
+  }
+
+  let albedo = max(textureLoad(albedo_texture, position).rgb, vec3f(MIN_ALBEDO));
+  let color = textureLoad(input_texture, position).rgb * albedo;
+  textureStore(output_texture, position, vec4(color, 1.0));
+  textureStore(render_texture, position, vec4(min(color, vec3f(1, 1, 1)), 1.0));
+}
@@ -0,0 +1,15 @@
+// The sky, the only light source.
+
+#include "common.wgsl"
+
+fn sky_color(ray_desc: RayDesc) -> vec3f {
+  let theta = atan2(ray_desc.dir.z, ray_desc.dir.x);
+  let phi = acos(ray_desc.dir.y);
+
+  let u = (theta + PI) / (2*PI);
+  let v = phi / PI;
+
+  let pos = vec2f(textureDimensions(skybox_texture).xy) * vec2f(u, v);
+
+  return textureLoad(skybox_texture, vec2u(pos)).rgb;
+}
@@ -0,0 +1,14 @@
+// Closest hit of a ray with the hardware acceleration structure, included with `RAY_QUERY` on
+// adapters with ray queries. `bvh.wgsl` implements the same function without them.
+
+#include "common.wgsl"
+
+@group(0) @binding(2)
+var acc_struct: acceleration_structure;
+
+fn intersect_closest(ray: RayDesc) -> RayIntersection {
+  var ray_query: ray_query;
+  rayQueryInitialize(&ray_query, acc_struct, ray);
+  rayQueryProceed(&ray_query);
+  return rayQueryGetCommittedIntersection(&ray_query);
+}
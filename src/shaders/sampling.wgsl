@@ -0,0 +1,124 @@
+// Random numbers and the low discrepancy sequences the samples of a pixel draw from.
+
+#include "common.wgsl"
+
+var<private> rng_state: u32;
+var<private> sample_index: u32;
+
+// Layers of `noise_array`. `textureNumLayers` of storage textures does not translate to GLSL for
+// the gl backend.
+const NUM_NOISE_LAYERS: u32 = 8;
+
+const SAMPLER_BLUE_NOISE: u32 = 0;
+const SAMPLER_SOBOL: u32 = 1;
+const SAMPLER_HALTON: u32 = 2;
+
+// 2D sample dimensions, every bounce draws from its own dimensions after the pixel jitter.
+const DIMENSION_PIXEL: u32 = 0;
+const DIMENSION_FIRST_BOUNCE: u32 = 1;
+const DIMENSIONS_PER_BOUNCE: u32 = 2;
+const DIMENSION_DIRECTION: u32 = 0;
+const DIMENSION_ROULETTE: u32 = 1;
+
+fn hash(value: u32) -> u32 {
+  var state = value;
+  state = (state ^ 61) ^ (state >> 16);
+  state *= 9;
+  state = state ^ (state >> 4);
+  state *= 0x27d4eb2d;
+  state = state ^ (state >> 15);
+  return state;
+}
+
+fn hash_combine(seed: u32, value: u32) -> u32 {
+  return hash(seed ^ hash(value));
+}
+
+fn rand_wang() -> u32 {
+  rng_state = hash(rng_state);
+  return rng_state;
+}
+
+// Maps the upper 24 bits to [0, 1), the precision of an f32 mantissa.
+fn unit_float(x: u32) -> f32 {
+  return f32(x >> 8) / 16777216.0;
+}
+
+fn dimension_seed(gid: vec3u, dimension: u32) -> u32 {
+  return hash_combine(hash_combine(hash_combine(push_constants.seed, gid.x), gid.y), dimension);
+}
+
+fn sobol(index: u32, dimension: u32) -> u32 {
+  var x = 0u;
+  for (var bit = 0u; bit < 32; bit++) {
+    if ((index >> bit) & 1) != 0 {
+      x ^= sampler_tables.sobol[dimension][bit];
+    }
+  }
+  return x;
+}
+
+// Owen scrambling of the bits of x with the Laine-Karras permutation.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+  var v = reverseBits(x) + seed;
+  v ^= v * 0x6c50b47cu;
+  v ^= v * 0xb82f1e52u;
+  v ^= v * 0xc7afe638u;
+  v ^= v * 0x8d22f6e6u;
+  return reverseBits(v);
+}
+
+// Burley's shuffled and Owen scrambled Sobol points, a separate sequence for every seed.
+fn sobol_2d(index: u32, seed: u32) -> vec2f {
+  let shuffled = nested_uniform_scramble(index, hash(seed));
+  return vec2f(
+    unit_float(nested_uniform_scramble(sobol(shuffled, 0), hash_combine(seed, 0))),
+    unit_float(nested_uniform_scramble(sobol(shuffled, 1), hash_combine(seed, 1)))
+  );
+}
+
+fn radical_inverse(index: u32, base: u32) -> f32 {
+  let inverse_base = 1.0 / f32(base);
+  var fraction = inverse_base;
+  var result = 0.0;
+  for (var i = index; i > 0; i /= base) {
+    result += f32(i % base) * fraction;
+    fraction *= inverse_base;
+  }
+  return result;
+}
+
+// The primes cover NUM_PRIMES / 2 dimensions, deeper ones use scrambled Sobol points instead of
+// repeating earlier dimensions.
+fn halton_2d(index: u32, dimension: u32, seed: u32) -> vec2f {
+  if dimension >= NUM_PRIMES / 2 {
+    return sobol_2d(index, seed);
+  }
+  let rotation = vec2f(unit_float(hash_combine(seed, 0)), unit_float(hash_combine(seed, 1)));
+  let point = vec2f(
+    radical_inverse(index, sampler_tables.primes[2 * dimension]),
+    radical_inverse(index, sampler_tables.primes[2 * dimension + 1])
+  );
+  return fract(point + rotation);
+}
+
+fn blue_noise_2d(gid: vec3u, dimension: u32) -> vec2f {
+  let noise_size = vec3(textureDimensions(noise_array), NUM_NOISE_LAYERS);
+  let random_offset = (vec3(gid.xy, dimension) + vec3(rand_wang(), rand_wang(), rand_wang())) % noise_size;
+  return textureLoad(noise_array, random_offset.xy, random_offset.z).rg;
+}
+
+// Point in [0, 1)^2 of the current sample for one 2D sample dimension.
+fn sample_2d(gid: vec3u, dimension: u32) -> vec2f {
+  switch push_constants.sampler_type {
+    case SAMPLER_SOBOL: {
+      return sobol_2d(sample_index, dimension_seed(gid, dimension));
+    }
+    case SAMPLER_HALTON: {
+      return halton_2d(sample_index, dimension, dimension_seed(gid, dimension));
+    }
+    default: {
+      return blue_noise_2d(gid, dimension);
+    }
+  }
+}
@@ -0,0 +1,328 @@
+// Path tracer, one invocation per pixel adds samples to the accumulation and writes what the
+// window shows to the render texture.
+//
+// Feature defines select the pipeline variants: `RAY_QUERY` traverses the hardware
+// acceleration structure instead of the bvh, `AOVS` writes the position and ID textures and
+// `DEBUG_VIEWS` shows the sample heatmap and the AOV views.
+
+#include "common.wgsl"
+#include "sampling.wgsl"
+#include "bsdf.wgsl"
+#include "camera.wgsl"
+#include "environment.wgsl"
+#include "debug_views.wgsl"
+#ifdef RAY_QUERY
+#include "ray_query.wgsl"
+#else
+#include "bvh.wgsl"
+#endif
+
+var<private> primary_hit: PrimaryHit;
+
+// Keeps the relative error of almost black pixels from demanding endless samples.
+const ADAPTIVE_MIN_LUMINANCE: f32 = 0.01;
+
+// What the dispatch starts from: the accumulation, nothing, or the reprojected history.
+const HISTORY_CONTINUE: u32 = 0;
+const HISTORY_RESTART: u32 = 1;
+const HISTORY_REPROJECT: u32 = 2;
+
+// Reflections move differently than the surfaces they are reprojected with, limiting the
+// reprojected samples lets them catch up.
+const MAX_HISTORY_SAMPLES: u32 = 64;
+// Relative distance difference beyond which the previous camera saw another surface.
+const DISOCCLUSION_TOLERANCE: f32 = 0.05;
+
+struct PrimaryHit {
+  normal: vec3f,
+  depth: f32,
+  albedo: vec3f,
+}
+
+struct FirstHit {
+  // Zero for the sky.
+  distance: f32,
+  material_id: f32,
+  instance_id: f32,
+}
+
+struct PointAovs {
+  position: vec4f,
+  // Material and instance ID.
+  ids: vec2f,
+}
+
+struct History {
+  accumulation: vec4f,
+  m2: f32,
+  normal_depth: vec4f,
+  albedo: vec3f,
+}
+
+fn trace_ray(ray_desc: RayDesc, gid: vec3u) -> vec3f {
+  var ray = ray_desc;
+  var color = vec3f(1, 1, 1);
+
+  var intersection = intersect_closest(ray);
+
+  for (var i = 0u; i < push_constants.max_depth; i++) {
+    rng_state += i * 2351341;
+    let dimension = DIMENSION_FIRST_BOUNCE + i * DIMENSIONS_PER_BOUNCE;
+
+    // Russian roulette: terminate paths that carry little light, and weight the survivors by the
+    // inverse survival probability to stay unbiased.
+    if i >= push_constants.rr_min_depth {
+      let probability = clamp(max(color.r, max(color.g, color.b)), push_constants.rr_min_probability, 1.0);
+      if sample_2d(gid, dimension + DIMENSION_ROULETTE).x >= probability {
+        break;
+      }
+      color /= probability;
+    }
+    if (intersection.kind != RAY_QUERY_INTERSECTION_NONE) {
+      if intersection.t < 0.001 {
+        break;
+      }
+
+      let n0 = vertices[intersection.primitive_index * 3 + 0].normal;
+      let n1 = vertices[intersection.primitive_index * 3 + 1].normal;
+      let n2 = vertices[intersection.primitive_index * 3 + 2].normal;
+      let material = materials[vertices[intersection.primitive_index * 3].material];
+
+      let u = intersection.barycentrics.x;
+      let v = intersection.barycentrics.y;
+      let w = 1.0 - u - v;
+
+      var normal = normalize(w * n0 + u * n1 + v * n2);
+      // Shade the side of the surface the ray arrives from.
+      if dot(normal, ray.dir) > 0.0 {
+        normal = -normal;
+      }
+
+      if i == 0 {
+        primary_hit = PrimaryHit(normal, intersection.t, surface_albedo(material));
+      }
+
+      ray.origin = ray.origin + ray.dir * intersection.t;
+      let scattered = sample_bsdf(material, normal, ray.dir, gid, dimension + DIMENSION_DIRECTION);
+      ray.dir = scattered.dir;
+      color *= scattered.weight;
+
+      intersection = intersect_closest(ray);
+    } else {
+      if ray.dir.y < 0.0 {
+        let t = -ray.origin.y / ray.dir.y;
+        let normal = vec3(0.0, 1.0, 0.0);
+
+        // The ground reflects the sky below the horizon.
+        if i == 0 {
+          primary_hit = PrimaryHit(normal, t, sky_color(ray));
+        }
+        color *= sky_color(ray);
+
+        ray.origin = ray.origin + ray.dir * t;
+        ray.dir = cosine_sample_hemisphere(normal, sample_2d(gid, dimension + DIMENSION_DIRECTION));
+
+        intersection = intersect_closest(ray);
+      } else {
+        return color * sky_color(ray);
+      }
+    }
+  }
+
+  // Terminated before reaching the sky, the only light source.
+  return vec3f(0, 0, 0);
+}
+
+fn first_hit(ray: RayDesc) -> FirstHit {
+  let intersection = intersect_closest(ray);
+  if intersection.kind != RAY_QUERY_INTERSECTION_NONE {
+    let material = vertices[intersection.primitive_index * 3].material;
+    return FirstHit(intersection.t, f32(material), f32(intersection.instance_id));
+  }
+  if ray.dir.y < 0.0 {
+    return FirstHit(-ray.origin.y / ray.dir.y, GROUND_ID, GROUND_ID);
+  }
+  return FirstHit(0.0, SKY_ID, SKY_ID);
+}
+
+// Samples the previous camera accumulated for the surface seen through the center of the pixel,
+// or none if that surface was off screen or hidden behind another one.
+fn reproject(gid: vec3u) -> History {
+  let no_history = History(vec4f(0, 0, 0, 0), 0.0, vec4f(0, 0, 0, 0), vec3f(0, 0, 0));
+  let size = vec2f(textureDimensions(render_texture));
+
+  let ray = primary_ray(pixel_to_ndc(vec2f(gid.xy), size));
+  let distance = first_hit(ray).distance;
+
+  var clip: vec4f;
+  var previous_distance = 0.0;
+  if distance > 0.0 {
+    let position = ray.origin + ray.dir * distance;
+    clip = camera.previous_view_proj * vec4(position, 1.0);
+
+    let offset = position - camera.previous_eye;
+    if camera.previous_projection == PROJECTION_ORTHOGRAPHIC {
+      previous_distance = dot(offset, camera.previous_forward);
+    } else {
+      previous_distance = length(offset);
+    }
+  } else {
+    // The sky is infinitely far away, only its direction matters.
+    clip = camera.previous_view_proj * vec4(ray.dir, 0.0);
+  }
+  if clip.w <= 0.0 {
+    return no_history;
+  }
+
+  let previous_ndc = clip.xy / clip.w;
+  let previous_pixel = vec2i(round(vec2f(
+    (previous_ndc.x + 1.0) * 0.5 * size.x,
+    (1.0 - previous_ndc.y) * 0.5 * size.y
+  )));
+  if any(previous_pixel < vec2i(0, 0)) || any(previous_pixel >= vec2i(size)) {
+    return no_history;
+  }
+
+  // The sky only matches the sky, which has zero distance in the history as well.
+  let normal_depth = textureLoad(history_normal_depth_texture, previous_pixel);
+  if abs(normal_depth.w - previous_distance) > DISOCCLUSION_TOLERANCE * previous_distance {
+    return no_history;
+  }
+
+  let accumulation = textureLoad(history_accumulation_texture, previous_pixel);
+  let history_samples = u32(accumulation.a);
+  if history_samples == 0 {
+    return no_history;
+  }
+  let num_samples = min(history_samples, MAX_HISTORY_SAMPLES);
+
+  // Fewer samples of the same mean keep the same variance, with proportionally smaller M2.
+  let m2 = textureLoad(history_variance_texture, previous_pixel).r * f32(num_samples) / f32(history_samples);
+  return History(
+    vec4(accumulation.rgb, f32(num_samples)),
+    m2,
+    normal_depth,
+    textureLoad(history_albedo_texture, previous_pixel).rgb
+  );
+}
+
+fn gamma_correct(color: vec3f) -> vec3f {
+  return pow(color, vec3(1.0 / 2.2));
+}
+
+fn luminance(color: vec3f) -> f32 {
+  return dot(color, vec3f(0.2126, 0.7152, 0.0722));
+}
+
+// Whether the standard error of the mean luminance has dropped below the adaptive threshold,
+// relative to the luminance itself.
+fn is_converged(mean: vec3f, m2: f32, count: u32) -> bool {
+  let min_samples = max(push_constants.adaptive_min_samples, 2u);
+  if push_constants.adaptive_threshold <= 0.0 || count < min_samples {
+    return false;
+  }
+  let standard_error = sqrt(m2 / f32(count - 1) / f32(count));
+  let tolerance = push_constants.adaptive_threshold * max(luminance(mean), ADAPTIVE_MIN_LUMINANCE);
+  return standard_error <= tolerance;
+}
+
+// The AOVs that are sampled at the pixel center.
+fn point_aovs(gid: vec3u) -> PointAovs {
+  let ray = primary_ray(pixel_to_ndc(vec2f(gid.xy), vec2f(textureDimensions(render_texture))));
+  let hit = first_hit(ray);
+
+  var position = vec4f(0, 0, 0, 0);
+  if hit.distance > 0.0 {
+    position = vec4(ray.origin + ray.dir * hit.distance, 1.0);
+  }
+  return PointAovs(position, vec2(hit.material_id, hit.instance_id));
+}
+
+@compute
+@workgroup_size(10, 10, 1)
+fn render(@builtin(global_invocation_id) gid: vec3u) {
+  if any(gid.xy >= textureDimensions(render_texture)) {
+    return;
+  }
+
+  let render_texture_size = vec2f(textureDimensions(render_texture).xy);
+
+  // The alpha channel of the accumulation counts the samples of the pixel, which differs between
+  // pixels with adaptive sampling.
+  var history: History;
+  switch push_constants.history {
+    case HISTORY_RESTART: {
+      history = History(vec4f(0, 0, 0, 0), 0.0, vec4f(0, 0, 0, 0), vec3f(0, 0, 0));
+    }
+    case HISTORY_REPROJECT: {
+      history = reproject(gid);
+    }
+    default: {
+      history = History(
+        textureLoad(accumulation_texture, gid.xy),
+        textureLoad(variance_texture, gid.xy).r,
+        textureLoad(normal_depth_texture, gid.xy),
+        textureLoad(albedo_texture, gid.xy).rgb
+      );
+    }
+  }
+  var accumulated_color = history.accumulation.rgb;
+  var pixel_samples = u32(history.accumulation.a);
+  var m2 = history.m2;
+  var normal_depth = history.normal_depth;
+  var albedo = history.albedo;
+
+  // Random sequences follow the pixel position in the whole image, which keeps the tiles of a
+  // tiled render from repeating the same noise.
+  let image_gid = vec3(gid.xy + camera.tile_offset, gid.z);
+
+  // Without any samples to add, the dispatch only copies the accumulation to the render texture.
+  for (var i = 0u; i < push_constants.dispatch_samples; i++) {
+    if is_converged(accumulated_color, m2, pixel_samples) {
+      break;
+    }
+    sample_index = push_constants.sample_offset + pixel_samples;
+
+    // Everything random, including the blue noise offsets, derives from this state or from
+    // dimension_seed, which makes the image a pure function of the seed and the sample count.
+    let seed_offset = push_constants.seed * 0x9e3779b9u;
+    rng_state = ((image_gid.x * 1973 + image_gid.y * 9277 + sample_index * 26699) ^ seed_offset) | 1;
+
+    let pixel = vec2f(gid.xy) + sample_2d(image_gid, DIMENSION_PIXEL) - 0.5;
+    primary_hit = PrimaryHit(vec3f(0, 0, 0), 0.0, vec3f(1, 1, 1));
+
+    let ndc = pixel_to_ndc(pixel, render_texture_size);
+    let ray_color = trace_ray(primary_ray(ndc), image_gid);
+
+    // Welford's online update of the mean and the squared deviations.
+    pixel_samples += 1;
+    let previous_mean = luminance(accumulated_color);
+    accumulated_color = mix(accumulated_color, ray_color, 1 / f32(pixel_samples));
+    normal_depth = mix(normal_depth, vec4(primary_hit.normal, primary_hit.depth), 1 / f32(pixel_samples));
+    albedo = mix(albedo, primary_hit.albedo, 1 / f32(pixel_samples));
+    let sample_luminance = luminance(ray_color);
+    m2 += (sample_luminance - previous_mean) * (sample_luminance - luminance(accumulated_color));
+  }
+
+  textureStore(accumulation_texture, gid.xy, vec4(accumulated_color, f32(pixel_samples)));
+  textureStore(variance_texture, gid.xy, vec4(m2, 0, 0, 0));
+  textureStore(normal_depth_texture, gid.xy, normal_depth);
+  textureStore(albedo_texture, gid.xy, vec4(albedo, 1.0));
+
+#ifdef AOVS
+  // Also written for the AOV views, which enable them.
+  let aovs = point_aovs(gid);
+  textureStore(position_texture, gid.xy, aovs.position);
+  textureStore(id_texture, gid.xy, vec4(aovs.ids, 0.0, 1.0));
+#else
+  let aovs = PointAovs(vec4f(0, 0, 0, 0), vec2(SKY_ID, SKY_ID));
+#endif
+
+#ifdef DEBUG_VIEWS
+  let color = debug_view_color(accumulated_color, pixel_samples, albedo, normal_depth, aovs.position, aovs.ids);
+#else
+  let color = accumulated_color;
+#endif
+  // The accumulation keeps the linear radiance, only the display is clamped.
+  textureStore(render_texture, gid.xy, vec4(min(color, vec3f(1, 1, 1)), 1.0));
+}
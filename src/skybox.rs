@@ -1,17 +1,21 @@
 use std::io::Cursor;
 
-use image::{EncodableLayout, GenericImageView, ImageFormat, ImageReader};
+use image::{EncodableLayout, ImageFormat, ImageReader, Rgba32FImage};
 use wgpu::{
     util::{DeviceExt, TextureDataOrder},
     Device, Extent3d, Queue, TextureDimension, TextureFormat, TextureUsages, TextureView,
     TextureViewDescriptor,
 };
 
-pub fn load_skybox(device: &Device, queue: &Queue) -> TextureView {
+pub fn load_skybox_image() -> Rgba32FImage {
     let skybox_bytes = include_bytes!("../assets/skybox/zwartkops_straight_afternoon_4k.hdr");
     let reader = ImageReader::with_format(Cursor::new(skybox_bytes), ImageFormat::Hdr);
     let image = reader.decode().unwrap();
-    let image_formatted = image.to_rgba32f();
+    image.to_rgba32f()
+}
+
+pub fn load_skybox(device: &Device, queue: &Queue) -> TextureView {
+    let image = load_skybox_image();
     let (width, height) = image.dimensions();
 
     let texture = device.create_texture_with_data(
@@ -31,7 +35,7 @@ pub fn load_skybox(device: &Device, queue: &Queue) -> TextureView {
             view_formats: &[],
         },
         TextureDataOrder::MipMajor,
-        image_formatted.as_bytes(),
+        image.as_bytes(),
     );
 
     texture.create_view(&TextureViewDescriptor::default())